@@ -0,0 +1,242 @@
+//! Whole-set operations on a few distributions of indices, compared with the current backend of
+//! mosaic and with `BTreeSet`.
+//!
+//! All indices stay below 2^21, the largest index `hi_sparse_bitset` accepts with 128-bit blocks.
+
+use std::collections::BTreeSet;
+
+use divan::{Bencher, black_box};
+use hi_sparse_bitset::BitSetInterface;
+use inbitset::BitSetView;
+
+fn main() {
+    divan::main();
+}
+
+type HiSparseBitSet = hi_sparse_bitset::BitSet<hi_sparse_bitset::config::_128bit>;
+
+const SETS: [Distribution; 3] = [
+    Distribution::Dense,
+    Distribution::Clustered,
+    Distribution::Sparse,
+];
+
+#[derive(Copy, Clone, Debug)]
+enum Distribution {
+    /// Half of the indices below 2^17.
+    Dense,
+    /// Half of the indices in 64 runs of 1024, anywhere below 2^21. The runs are in the same place
+    /// for every seed.
+    Clustered,
+    /// 10 000 indices anywhere below 2^21.
+    Sparse,
+}
+
+impl Distribution {
+    /// Returns the indices of a set, in random order. Every seed gives a different set.
+    fn indices(self, seed: u64) -> Vec<usize> {
+        let mut rng = Rng(seed);
+        let mut runs = Rng(u64::MAX);
+        let mut indices: Vec<usize> = match self {
+            Self::Dense => (0..1 << 17).filter(|_| rng.next() & 1 == 0).collect(),
+            Self::Clustered => (0..64)
+                .flat_map(|_| {
+                    let start = runs.below((1 << 21) - 1024);
+                    (start..start + 1024).collect::<Vec<_>>()
+                })
+                .filter(|_| rng.next() & 1 == 0)
+                .collect(),
+            Self::Sparse => (0..10_000).map(|_| rng.below(1 << 21)).collect(),
+        };
+        for i in (1..indices.len()).rev() {
+            indices.swap(i, rng.below(i + 1));
+        }
+        indices
+    }
+}
+
+/// SplitMix64, so the sets are the same in every run without a dependency on `rand`.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+/// The operations under test, on each of the compared sets.
+trait Set: Sized {
+    fn new() -> Self;
+    fn insert(&mut self, index: usize);
+    fn remove(&mut self, index: usize);
+    fn contains(&self, index: usize) -> bool;
+    /// Iterates the set, returning the sum of its indices.
+    fn sum(&self) -> usize;
+    /// Iterates the intersection of the sets, returning the sum of its indices.
+    fn and_sum(a: &Self, b: &Self) -> usize;
+    fn and4_sum(sets: &[Self; 4]) -> usize;
+
+    fn from_indices(indices: &[usize]) -> Self {
+        let mut set = Self::new();
+        for &index in indices {
+            set.insert(index);
+        }
+        set
+    }
+}
+
+impl Set for inbitset::BitSet {
+    fn new() -> Self {
+        Self::new()
+    }
+
+    fn insert(&mut self, index: usize) {
+        self.insert(index);
+    }
+
+    fn remove(&mut self, index: usize) {
+        self.remove(index);
+    }
+
+    fn contains(&self, index: usize) -> bool {
+        self.contains(index)
+    }
+
+    fn sum(&self) -> usize {
+        self.iter().sum()
+    }
+
+    fn and_sum(a: &Self, b: &Self) -> usize {
+        inbitset::apply(inbitset::ops::And, a, b).iter().sum()
+    }
+
+    fn and4_sum(sets: &[Self; 4]) -> usize {
+        inbitset::reduce(inbitset::ops::And, sets).iter().sum()
+    }
+}
+
+impl Set for HiSparseBitSet {
+    fn new() -> Self {
+        Self::new()
+    }
+
+    fn insert(&mut self, index: usize) {
+        self.insert(index);
+    }
+
+    fn remove(&mut self, index: usize) {
+        self.remove(index);
+    }
+
+    fn contains(&self, index: usize) -> bool {
+        BitSetInterface::contains(&self, index)
+    }
+
+    fn sum(&self) -> usize {
+        self.iter().sum()
+    }
+
+    fn and_sum(a: &Self, b: &Self) -> usize {
+        hi_sparse_bitset::apply(hi_sparse_bitset::ops::And, a, b)
+            .iter()
+            .sum()
+    }
+
+    fn and4_sum(sets: &[Self; 4]) -> usize {
+        hi_sparse_bitset::reduce(hi_sparse_bitset::ops::And, sets.iter())
+            .expect("there are four sets")
+            .iter()
+            .sum()
+    }
+}
+
+impl Set for BTreeSet<usize> {
+    fn new() -> Self {
+        Self::new()
+    }
+
+    fn insert(&mut self, index: usize) {
+        self.insert(index);
+    }
+
+    fn remove(&mut self, index: usize) {
+        self.remove(&index);
+    }
+
+    fn contains(&self, index: usize) -> bool {
+        self.contains(&index)
+    }
+
+    fn sum(&self) -> usize {
+        self.iter().sum()
+    }
+
+    fn and_sum(a: &Self, b: &Self) -> usize {
+        a.intersection(b).sum()
+    }
+
+    fn and4_sum([a, b, c, d]: &[Self; 4]) -> usize {
+        a.intersection(b)
+            .filter(|index| c.contains(index) && d.contains(index))
+            .sum()
+    }
+}
+
+#[divan::bench(types = [inbitset::BitSet, HiSparseBitSet, BTreeSet<usize>], args = SETS)]
+fn insert<S: Set>(bencher: Bencher, distribution: Distribution) {
+    let indices = distribution.indices(0);
+    bencher.bench_local(|| S::from_indices(black_box(&indices)));
+}
+
+#[divan::bench(types = [inbitset::BitSet, HiSparseBitSet, BTreeSet<usize>], args = SETS)]
+fn remove<S: Set>(bencher: Bencher, distribution: Distribution) {
+    let indices = distribution.indices(0);
+    bencher
+        .with_inputs(|| S::from_indices(&indices))
+        .bench_local_refs(|set| {
+            for &index in black_box(&indices) {
+                set.remove(index);
+            }
+        });
+}
+
+/// Looks up the indices of another set of the same distribution, so about as many hit as the
+/// density of the set.
+#[divan::bench(types = [inbitset::BitSet, HiSparseBitSet, BTreeSet<usize>], args = SETS)]
+fn contains<S: Set>(bencher: Bencher, distribution: Distribution) {
+    let set = S::from_indices(&distribution.indices(0));
+    let queries = distribution.indices(1);
+    bencher.bench_local(|| {
+        black_box(&queries)
+            .iter()
+            .filter(|&&index| set.contains(index))
+            .count()
+    });
+}
+
+#[divan::bench(types = [inbitset::BitSet, HiSparseBitSet, BTreeSet<usize>], args = SETS)]
+fn iter<S: Set>(bencher: Bencher, distribution: Distribution) {
+    let set = S::from_indices(&distribution.indices(0));
+    bencher.bench_local(|| black_box(&set).sum());
+}
+
+#[divan::bench(types = [inbitset::BitSet, HiSparseBitSet, BTreeSet<usize>], args = SETS)]
+fn and<S: Set>(bencher: Bencher, distribution: Distribution) {
+    let a = S::from_indices(&distribution.indices(0));
+    let b = S::from_indices(&distribution.indices(1));
+    bencher.bench_local(|| S::and_sum(black_box(&a), black_box(&b)));
+}
+
+#[divan::bench(types = [inbitset::BitSet, HiSparseBitSet, BTreeSet<usize>], args = SETS)]
+fn and4<S: Set>(bencher: Bencher, distribution: Distribution) {
+    let sets = [0, 1, 2, 3].map(|seed| S::from_indices(&distribution.indices(seed)));
+    bencher.bench_local(|| S::and4_sum(black_box(&sets)));
+}
@@ -0,0 +1,108 @@
+//! Drives two `BitSet`s and two `BTreeSet`s with the same operations and checks that they agree,
+//! like `tests/model.rs` does with proptest.
+
+#![no_main]
+
+use std::collections::BTreeSet;
+
+use arbitrary::Arbitrary;
+use inbitset::{BitSet, BitSetView};
+use libfuzzer_sys::fuzz_target;
+
+#[derive(Arbitrary, Debug)]
+enum Index {
+    /// Within the first few bottom blocks.
+    Small(u16),
+    /// Next to the start of the `n`-th bottom, middle or top block.
+    Boundary { level: u8, n: u8, offset: i8 },
+    Any(usize),
+}
+
+impl Index {
+    fn get(&self) -> usize {
+        match *self {
+            Index::Small(index) => index as usize,
+            Index::Boundary { level, n, offset } => {
+                let cap = 1usize << (8 * (level % 3 + 1));
+                (cap * n as usize).wrapping_add_signed(offset as isize)
+            }
+            Index::Any(index) => index,
+        }
+    }
+}
+
+#[derive(Arbitrary, Debug)]
+enum Op {
+    Insert(bool, Index),
+    Remove(bool, Index),
+    Contains(bool, Index),
+    Rank(bool, Index),
+    Select(bool, u16),
+    NextAfter(bool, Index),
+    PrevBefore(bool, Index),
+    PopFirst(bool),
+    PopLast(bool),
+    Check,
+}
+
+fuzz_target!(|ops: Vec<Op>| {
+    let mut sets = [BitSet::new(), BitSet::new()];
+    let mut models = [BTreeSet::new(), BTreeSet::new()];
+
+    for op in ops {
+        match op {
+            Op::Insert(side, index) => {
+                let (set, model) = (&mut sets[side as usize], &mut models[side as usize]);
+                assert_eq!(set.insert(index.get()), model.insert(index.get()));
+            }
+            Op::Remove(side, index) => {
+                let (set, model) = (&mut sets[side as usize], &mut models[side as usize]);
+                assert_eq!(set.remove(index.get()), model.remove(&index.get()));
+            }
+            Op::Contains(side, index) => {
+                let (set, model) = (&sets[side as usize], &models[side as usize]);
+                assert_eq!(set.contains(index.get()), model.contains(&index.get()));
+            }
+            Op::Rank(side, index) => {
+                let (set, model) = (&sets[side as usize], &models[side as usize]);
+                assert_eq!(set.rank(index.get()), model.range(..index.get()).count());
+            }
+            Op::Select(side, n) => {
+                let (set, model) = (&sets[side as usize], &models[side as usize]);
+                let n = n as usize;
+                assert_eq!(set.select(n), model.iter().nth(n).copied());
+            }
+            Op::NextAfter(side, index) => {
+                let (set, model) = (&sets[side as usize], &models[side as usize]);
+                let next = match index.get().checked_add(1) {
+                    Some(start) => model.range(start..).next().copied(),
+                    None => None,
+                };
+                assert_eq!(set.next_after(index.get()), next);
+            }
+            Op::PrevBefore(side, index) => {
+                let (set, model) = (&sets[side as usize], &models[side as usize]);
+                let prev = model.range(..index.get()).next_back().copied();
+                assert_eq!(set.prev_before(index.get()), prev);
+            }
+            Op::PopFirst(side) => {
+                assert_eq!(sets[side as usize].pop_first(), models[side as usize].pop_first());
+            }
+            Op::PopLast(side) => {
+                assert_eq!(sets[side as usize].pop_last(), models[side as usize].pop_last());
+            }
+            Op::Check => {
+                let [a, b] = &sets;
+                let [ma, mb] = &models;
+                assert!(a.iter().eq(ma.iter().copied()));
+                assert!(b.iter().eq(mb.iter().copied()));
+                assert!(a.iter().rev().eq(ma.iter().rev().copied()));
+                assert_eq!((a.len(), b.len()), (ma.len(), mb.len()));
+                assert!(a.and(b).iter().eq(ma.intersection(mb).copied()));
+                assert!(a.or(b).iter().eq(ma.union(mb).copied()));
+                assert!(a.xor(b).iter().eq(ma.symmetric_difference(mb).copied()));
+                assert!(a.and_not(b).iter().eq(ma.difference(mb).copied()));
+            }
+        }
+    }
+});
@@ -0,0 +1,216 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{
+    BitSet, BitSetView, Bits, LevelIndices,
+    bits::{SCALAR_BITS, Scalar},
+    config,
+};
+
+/// Bitset with a fixed capacity that can be modified concurrently through a shared reference.
+///
+/// Uses the same three-level hierarchy as [`BitSet`], but all blocks for the indices below the
+/// capacity are reserved up front, laid out in the order of their paths. Blocks never have to be
+/// allocated or linked, so inserts and removes are a few atomic `fetch_or` and `fetch_and`
+/// operations on the lanes of the blocks, without any locks.
+///
+/// The occupancy bits of the top and middle blocks are only ever set concurrently, since a block
+/// emptied by a remove may be refilled by another thread at the same time. They are conservative,
+/// as allowed by [`BitSetView`], until [`Self::clear`] or the conversion into a [`BitSet`].
+///
+/// All accesses are relaxed, so a bit inserted by one thread is only guaranteed to be visible to
+/// another after they synchronize by other means, like joining the thread.
+pub struct AtomicBitSet<const LANES: usize = 4> {
+    capacity: usize,
+    tops: Box<[AtomicBits<LANES>]>,
+    middle: Box<[AtomicBits<LANES>]>,
+    bottom: Box<[AtomicBits<LANES>]>,
+}
+
+impl<const LANES: usize> AtomicBitSet<LANES> {
+    /// Creates an empty set for the indices below `capacity`, reserving all of its blocks.
+    pub fn with_capacity(capacity: usize) -> Self {
+        let blocks = |cap: usize| (0..capacity.div_ceil(cap)).map(|_| AtomicBits::default());
+        Self {
+            capacity,
+            tops: blocks(LevelIndices::<LANES>::TOP_BLOCK_CAP).collect(),
+            middle: blocks(LevelIndices::<LANES>::MIDDLE_BLOCK_CAP).collect(),
+            bottom: blocks(LevelIndices::<LANES>::BOTTOM_BLOCK_CAP).collect(),
+        }
+    }
+
+    /// Returns the number of indices the set can hold, all indices are less than it.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Sets the bit at `bit_index`. Returns true if it was not set before.
+    ///
+    /// # Panics
+    /// Panics if `bit_index` is not less than the capacity.
+    pub fn insert(&self, bit_index: usize) -> bool {
+        assert!(bit_index < self.capacity, "bit index out of capacity");
+        let indices = LevelIndices::<LANES>::new(bit_index);
+
+        let bottom_block = &self.bottom[bit_index / LevelIndices::<LANES>::BOTTOM_BLOCK_CAP];
+        if bottom_block.fetch_set(indices.bottom) {
+            return false;
+        }
+        // The parents stay marked until the set is cleared, so they only need to be set.
+        self.middle[bit_index / LevelIndices::<LANES>::MIDDLE_BLOCK_CAP].set(indices.middle);
+        self.tops[indices.key].set(indices.top);
+        true
+    }
+
+    /// Unsets the bit at `bit_index`. Returns true if it was set. Emptied blocks stay marked as
+    /// occupied in their parents.
+    pub fn remove(&self, bit_index: usize) -> bool {
+        if bit_index >= self.capacity {
+            return false;
+        }
+        let indices = LevelIndices::<LANES>::new(bit_index);
+        self.bottom[bit_index / LevelIndices::<LANES>::BOTTOM_BLOCK_CAP].fetch_unset(indices.bottom)
+    }
+
+    pub fn contains(&self, bit_index: usize) -> bool {
+        if bit_index >= self.capacity {
+            return false;
+        }
+        let indices = LevelIndices::<LANES>::new(bit_index);
+        self.bottom[bit_index / LevelIndices::<LANES>::BOTTOM_BLOCK_CAP].get(indices.bottom)
+    }
+
+    /// Unsets all bits, visiting only the blocks that are marked as occupied.
+    pub fn clear(&mut self) {
+        for (key, top_block) in self.tops.iter_mut().enumerate() {
+            top_block.take().for_each_set(|top| {
+                let middle_block_index = key * Bits::<LANES>::SIZE + top;
+                self.middle[middle_block_index]
+                    .take()
+                    .for_each_set(|middle| {
+                        let bottom_block_index = middle_block_index * Bits::<LANES>::SIZE + middle;
+                        self.bottom[bottom_block_index].take();
+                    });
+            });
+        }
+    }
+
+    /// Returns the index of the middle block under the `top` bit of the top block with `key`, if
+    /// it is within the capacity.
+    fn middle_block_index(&self, key: usize, top: usize) -> Option<usize> {
+        let index = key.checked_mul(Bits::<LANES>::SIZE)? + top;
+        (index < self.middle.len()).then_some(index)
+    }
+}
+
+/// Handles are the key of the top block and the index of the middle block, all blocks are laid out
+/// by their paths.
+impl<const LANES: usize> BitSetView<LANES> for AtomicBitSet<LANES> {
+    type Top = Option<usize>;
+    type Middle = Option<usize>;
+
+    fn next_top(&self, key: usize) -> Option<usize> {
+        (key..self.tops.len()).find(|&key| !self.tops[key].load().is_empty())
+    }
+
+    fn prev_top(&self, key: usize) -> Option<usize> {
+        let end = self.tops.len().min(key.saturating_add(1));
+        (0..end).rfind(|&key| !self.tops[key].load().is_empty())
+    }
+
+    fn enter_top(&self, key: usize) -> (Self::Top, Bits<LANES>) {
+        match self.tops.get(key) {
+            Some(top_block) => (Some(key), top_block.load()),
+            None => (None, Bits::default()),
+        }
+    }
+
+    fn enter_middle(&self, parent: &Self::Top, top: usize) -> (Self::Middle, Bits<LANES>) {
+        assert!(top < Bits::<LANES>::SIZE, "top index out of range");
+        let middle_block_index = parent.and_then(|key| self.middle_block_index(key, top));
+        match middle_block_index {
+            Some(middle_block_index) => (
+                Some(middle_block_index),
+                self.middle[middle_block_index].load(),
+            ),
+            None => (None, Bits::default()),
+        }
+    }
+
+    fn enter_bottom(&self, parent: &Self::Middle, middle: usize) -> Bits<LANES> {
+        assert!(middle < Bits::<LANES>::SIZE, "middle index out of range");
+        let Some(middle_block_index) = *parent else {
+            return Bits::default();
+        };
+        self.bottom
+            .get(middle_block_index * Bits::<LANES>::SIZE + middle)
+            .map(AtomicBits::load)
+            .unwrap_or_default()
+    }
+
+    fn contains(&self, bit_index: usize) -> bool {
+        AtomicBitSet::contains(self, bit_index)
+    }
+}
+
+impl<const LANES: usize> From<AtomicBitSet<LANES>> for BitSet<config::Wide, LANES> {
+    fn from(set: AtomicBitSet<LANES>) -> Self {
+        set.materialize()
+    }
+}
+
+/// A block of bits with atomic lanes.
+struct AtomicBits<const LANES: usize> {
+    lanes: [AtomicU64; LANES],
+}
+
+impl<const LANES: usize> Default for AtomicBits<LANES> {
+    fn default() -> Self {
+        Self {
+            lanes: std::array::from_fn(|_| AtomicU64::new(0)),
+        }
+    }
+}
+
+impl<const LANES: usize> AtomicBits<LANES> {
+    fn load(&self) -> Bits<LANES> {
+        Bits::from_lanes(std::array::from_fn(|lane| {
+            self.lanes[lane].load(Ordering::Relaxed)
+        }))
+    }
+
+    /// Unsets all bits, returning the previous ones.
+    fn take(&mut self) -> Bits<LANES> {
+        Bits::from_lanes(std::array::from_fn(|lane| {
+            std::mem::take(self.lanes[lane].get_mut())
+        }))
+    }
+
+    fn get(&self, bit_index: usize) -> bool {
+        let (lane, mask) = Self::lane_mask(bit_index);
+        self.lanes[lane].load(Ordering::Relaxed) & mask != 0
+    }
+
+    /// Sets the bit at `bit_index`, skipping the write if it is set already.
+    fn set(&self, bit_index: usize) {
+        let (lane, mask) = Self::lane_mask(bit_index);
+        if self.lanes[lane].load(Ordering::Relaxed) & mask == 0 {
+            self.lanes[lane].fetch_or(mask, Ordering::Relaxed);
+        }
+    }
+
+    /// Sets the bit at `bit_index`. Returns the previous value.
+    fn fetch_set(&self, bit_index: usize) -> bool {
+        let (lane, mask) = Self::lane_mask(bit_index);
+        self.lanes[lane].fetch_or(mask, Ordering::Relaxed) & mask != 0
+    }
+
+    /// Unsets the bit at `bit_index`. Returns the previous value.
+    fn fetch_unset(&self, bit_index: usize) -> bool {
+        let (lane, mask) = Self::lane_mask(bit_index);
+        self.lanes[lane].fetch_and(!mask, Ordering::Relaxed) & mask != 0
+    }
+
+    fn lane_mask(bit_index: usize) -> (usize, Scalar) {
+        (bit_index / SCALAR_BITS, 1 << (bit_index % SCALAR_BITS))
+    }
+}
@@ -1,33 +1,142 @@
+#[cfg(feature = "simd")]
+use std::simd::{Simd, num::SimdUint};
 use std::{
-    mem,
-    simd::{Simd, num::SimdUint},
+    fmt, mem,
+    ops::{BitAnd, BitOr, BitXor, Not},
 };
 
-type Scalar = u64;
+pub(crate) type Scalar = u64;
 
-const SIMD_LANES: usize = 4;
-const SCALAR_BITS: usize = mem::size_of::<Scalar>() * 8;
+/// Storage of the lanes of a block, a SIMD vector with the `simd` feature and a plain array
+/// otherwise. Both index the same way, so only the whole-block operations differ.
+#[cfg(feature = "simd")]
+type Lanes<const LANES: usize> = Simd<Scalar, LANES>;
+#[cfg(not(feature = "simd"))]
+type Lanes<const LANES: usize> = [Scalar; LANES];
 
+pub(crate) const SCALAR_BITS: usize = mem::size_of::<Scalar>() * 8;
+
+/// A single block of bits, the unit every level of the hierarchy is built from.
+///
+/// A block is `LANES` scalars wide, so it holds `LANES * 64` bits. The supported geometries are
+/// 1, 2, 4 and 8 lanes, that is 64, 128, 256 and 512 bits per block.
 #[repr(transparent)]
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Debug)]
-pub(crate) struct Bits {
-    bits: Simd<Scalar, SIMD_LANES>,
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Bits<const LANES: usize = 4> {
+    bits: Lanes<LANES>,
+}
+
+impl<const LANES: usize> Default for Bits<LANES> {
+    fn default() -> Self {
+        #[cfg(feature = "simd")]
+        let bits = Simd::splat(0);
+        #[cfg(not(feature = "simd"))]
+        let bits = [0; LANES];
+        Self { bits }
+    }
 }
 
-impl Bits {
+impl<const LANES: usize> Bits<LANES> {
+    /// Number of bits in a block.
+    pub(crate) const SIZE: usize = {
+        assert!(
+            matches!(LANES, 1 | 2 | 4 | 8),
+            "unsupported block geometry, use 1, 2, 4 or 8 lanes"
+        );
+        LANES * SCALAR_BITS
+    };
+
+    /// Returns a block with the given lanes, lowest bits first.
+    pub(crate) fn from_lanes(lanes: [Scalar; LANES]) -> Self {
+        #[cfg(feature = "simd")]
+        let bits = Simd::from_array(lanes);
+        #[cfg(not(feature = "simd"))]
+        let bits = lanes;
+        Self { bits }
+    }
+
+    /// Returns the lanes of the block, lowest bits first.
+    pub(crate) fn to_lanes(self) -> [Scalar; LANES] {
+        #[cfg(feature = "simd")]
+        let lanes = self.bits.to_array();
+        #[cfg(not(feature = "simd"))]
+        let lanes = self.bits;
+        lanes
+    }
+
     /// Returns true if the block does not have any set bits.
     pub(crate) fn is_empty(&self) -> bool {
-        self.bits.reduce_or() == 0
+        #[cfg(feature = "simd")]
+        let any = self.bits.reduce_or();
+        #[cfg(not(feature = "simd"))]
+        let any = self.bits.iter().fold(0, |acc, lane| acc | lane);
+        any == 0
     }
 
     /// Returns the number of set bits in the block.
     pub(crate) fn count_set(&self) -> usize {
-        self.bits.count_ones().reduce_sum() as usize
+        #[cfg(feature = "simd")]
+        let count = self.bits.count_ones().reduce_sum() as usize;
+        #[cfg(not(feature = "simd"))]
+        let count = self
+            .bits
+            .iter()
+            .map(|lane| lane.count_ones() as usize)
+            .sum();
+        count
+    }
+
+    /// Returns a block with all bits below `bit_index` set. Values of `bit_index` greater than
+    /// `Self::SIZE` are clamped.
+    pub(crate) fn ones_below(bit_index: usize) -> Self {
+        let mut bits = Self::default();
+        for lane in 0..LANES {
+            let start = lane * SCALAR_BITS;
+            bits.bits[lane] = match bit_index.saturating_sub(start) {
+                0 => 0,
+                n if n >= SCALAR_BITS => Scalar::MAX,
+                n => (1 << n) - 1,
+            };
+        }
+        bits
+    }
+
+    /// Returns a block with the bits from `from` to `to`, both inclusive, set.
+    pub(crate) fn ones_between(from: usize, to: usize) -> Self {
+        Self::ones_below(to + 1) & !Self::ones_below(from)
+    }
+
+    /// Returns the index of the `n`-th set bit, counting from zero, or `None` if there are not
+    /// enough set bits.
+    pub(crate) fn select(&self, mut n: usize) -> Option<usize> {
+        for lane in 0..LANES {
+            let mut value = self.bits[lane];
+            let count = value.count_ones() as usize;
+            if n < count {
+                for _ in 0..n {
+                    value &= value - 1; // Clear the lowest set bit.
+                }
+                return Some(lane * SCALAR_BITS + value.trailing_zeros() as usize);
+            }
+            n -= count;
+        }
+        None
+    }
+
+    /// Splits the set bits into the lower and upper half, by their count. The lower half gets the
+    /// smaller one if the count is odd.
+    #[cfg(feature = "rayon")]
+    pub(crate) fn split_half(&self) -> (Self, Self) {
+        let Some(mid) = self.select(self.count_set() / 2) else {
+            return (*self, Self::default());
+        };
+        let lower = Self::ones_below(mid);
+        (*self & lower, *self & !lower)
     }
 
     /// Execute `f` for each set bit, from the lowest to highest, passing it the bit index.
     pub(crate) fn for_each_set(&self, mut f: impl FnMut(usize)) {
-        for lane in 0..SIMD_LANES {
+        for lane in 0..LANES {
             let mut value = self.bits[lane];
             while value != 0 {
                 let bit = value.trailing_zeros() as usize;
@@ -38,6 +147,62 @@ impl Bits {
         }
     }
 
+    /// Execute `f` for each set bit, from the highest to lowest, passing it the bit index.
+    pub(crate) fn for_each_set_rev(&self, mut f: impl FnMut(usize)) {
+        for lane in (0..LANES).rev() {
+            let mut value = self.bits[lane];
+            while value != 0 {
+                let bit = SCALAR_BITS - 1 - value.leading_zeros() as usize;
+                let bit_index = lane * SCALAR_BITS + bit;
+                f(bit_index);
+                value &= !(1 << bit); // Clear the highest set bit.
+            }
+        }
+    }
+
+    /// Returns the index of the lowest set bit, or `None` if the block is empty.
+    pub(crate) fn first_set(&self) -> Option<usize> {
+        for lane in 0..LANES {
+            let value = self.bits[lane];
+            if value != 0 {
+                return Some(lane * SCALAR_BITS + value.trailing_zeros() as usize);
+            }
+        }
+        None
+    }
+
+    /// Returns the index of the highest set bit, or `None` if the block is empty.
+    pub(crate) fn last_set(&self) -> Option<usize> {
+        for lane in (0..LANES).rev() {
+            let value = self.bits[lane];
+            if value != 0 {
+                let bit = SCALAR_BITS - 1 - value.leading_zeros() as usize;
+                return Some(lane * SCALAR_BITS + bit);
+            }
+        }
+        None
+    }
+
+    /// Unsets the highest set bit and returns its index, or `None` if the block is empty.
+    pub(crate) fn pop_last_set(&mut self) -> Option<usize> {
+        let bit_index = self.last_set()?;
+        self.bits[bit_index / SCALAR_BITS] &= !(1 << (bit_index % SCALAR_BITS));
+        Some(bit_index)
+    }
+
+    /// Unsets the lowest set bit and returns its index, or `None` if the block is empty.
+    pub(crate) fn pop_first_set(&mut self) -> Option<usize> {
+        for lane in 0..LANES {
+            let value = &mut self.bits[lane];
+            if *value != 0 {
+                let bit = value.trailing_zeros() as usize;
+                *value &= *value - 1; // Clear the lowest set bit.
+                return Some(lane * SCALAR_BITS + bit);
+            }
+        }
+        None
+    }
+
     /// Returns the value of the bit at the given index without bounds checking.
     ///
     /// # Safety
@@ -76,14 +241,49 @@ impl Bits {
     }
 
     pub(crate) fn take_empty_block(&mut self) -> usize {
-        let array = self.bits.as_mut_array();
-        let empty = array[0];
-        array[0] = 0;
-        empty as usize
+        mem::take(&mut self.bits[0]) as usize
     }
 
     pub(crate) fn set_empty_block(&mut self, empty: usize) {
-        let array = self.bits.as_mut_array();
-        array[0] = empty as Scalar;
+        self.bits[0] = empty as Scalar;
+    }
+}
+
+/// Formats the set bits as ranges, like `{0..=9, 15}`.
+impl<const LANES: usize> fmt::Debug for Bits<LANES> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut bits = *self;
+        crate::fmt_ranges(f, std::iter::from_fn(|| bits.pop_first_set()))
+    }
+}
+
+// Without the `simd` feature the lane-wise array operations are left to auto-vectorization.
+macro_rules! impl_bit_op {
+    ($($trait:ident::$method:ident => $op:tt),*) => {$(
+        impl<const LANES: usize> $trait for Bits<LANES> {
+            type Output = Self;
+
+            fn $method(self, rhs: Self) -> Self {
+                #[cfg(feature = "simd")]
+                let bits = self.bits $op rhs.bits;
+                #[cfg(not(feature = "simd"))]
+                let bits = std::array::from_fn(|lane| self.bits[lane] $op rhs.bits[lane]);
+                Self { bits }
+            }
+        }
+    )*};
+}
+
+impl_bit_op!(BitAnd::bitand => &, BitOr::bitor => |, BitXor::bitxor => ^);
+
+impl<const LANES: usize> Not for Bits<LANES> {
+    type Output = Self;
+
+    fn not(self) -> Self {
+        #[cfg(feature = "simd")]
+        let bits = !self.bits;
+        #[cfg(not(feature = "simd"))]
+        let bits = self.bits.map(|lane| !lane);
+        Self { bits }
     }
 }
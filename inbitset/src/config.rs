@@ -0,0 +1,61 @@
+//! Configurations of the [`BitSet`](crate::BitSet) layout.
+//!
+//! Top and middle blocks store the indices of their children in the pool of the level below.
+//! Narrower index types make those blocks smaller, but limit how many blocks the level below can
+//! have before inserting panics.
+
+use std::{fmt::Debug, hash::Hash};
+
+/// Compile-time configuration of a [`BitSet`](crate::BitSet).
+pub trait Config: 'static {
+    /// Type of the middle block indices stored in top blocks.
+    type TopIndex: BlockIndex;
+
+    /// Type of the bottom block indices stored in middle blocks.
+    type MiddleIndex: BlockIndex;
+}
+
+/// Integer type used to store the index of a block.
+pub trait BlockIndex: Copy + Default + Eq + Ord + Hash + Debug + 'static {
+    /// Converts `index`, or returns `None` if it does not fit.
+    fn from_usize(index: usize) -> Option<Self>;
+
+    fn to_usize(self) -> usize;
+}
+
+macro_rules! impl_block_index {
+    ($($ty:ty),*) => {
+        $(
+            impl BlockIndex for $ty {
+                fn from_usize(index: usize) -> Option<Self> {
+                    index.try_into().ok()
+                }
+
+                fn to_usize(self) -> usize {
+                    self as usize
+                }
+            }
+        )*
+    };
+}
+
+impl_block_index!(u8, u16, u32, u64, usize);
+
+/// 32-bit block indices, enough for any realistic number of blocks. The default.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Debug)]
+pub struct Wide;
+
+impl Config for Wide {
+    type TopIndex = u32;
+    type MiddleIndex = u32;
+}
+
+/// 16-bit block indices, for sets of up to 65536 middle and 65536 bottom blocks, at half the size
+/// of [`Wide`] top and middle blocks.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Debug)]
+pub struct Compact;
+
+impl Config for Compact {
+    type TopIndex = u16;
+    type MiddleIndex = u16;
+}
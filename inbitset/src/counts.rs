@@ -0,0 +1,165 @@
+use std::{mem, ops::Range};
+
+/// Number of counts that share one sum.
+const CHUNK: usize = 64;
+
+/// List of counts, one per top block of a set, with logarithmic prefix sums and search.
+///
+/// The counts are grouped into chunks of `CHUNK`, whose sums are kept in a Fenwick tree. Inserting
+/// or removing a count moves one count between each pair of later chunks and rebuilds the tree from
+/// its chunk on, which is cheap next to shifting the list of top blocks itself. Adding to a count
+/// updates a logarithmic number of sums, and the remainder of a chunk is summed directly.
+#[derive(Clone, Default, Debug)]
+pub(crate) struct Counts {
+    counts: Vec<u32>,
+    chunks: Vec<usize>, // Sum of each chunk.
+    /// Fenwick tree over the chunk sums: entry `i` (counting from one) holds the sum of the
+    /// `i & i.wrapping_neg()` chunks ending at chunk `i`, and is stored at `i - 1`.
+    sums: Vec<usize>,
+}
+
+impl Counts {
+    /// Replaces all counts, keeping the allocations.
+    pub(crate) fn rebuild(&mut self, counts: impl IntoIterator<Item = usize>) {
+        self.counts.clear();
+        self.counts
+            .extend(counts.into_iter().map(|count| count as u32));
+        self.chunks.clear();
+        self.chunks.extend(
+            self.counts
+                .chunks(CHUNK)
+                .map(|chunk| chunk.iter().map(|&count| count as usize).sum::<usize>()),
+        );
+        self.rebuild_from(0);
+    }
+
+    pub(crate) fn memory_usage(&self) -> usize {
+        self.counts.capacity() * mem::size_of::<u32>()
+            + (self.chunks.capacity() + self.sums.capacity()) * mem::size_of::<usize>()
+    }
+
+    pub(crate) fn shrink_to_fit(&mut self) {
+        self.counts.shrink_to_fit();
+        self.chunks.shrink_to_fit();
+        self.sums.shrink_to_fit();
+    }
+
+    /// Inserts a zero count at `position`, shifting the later ones.
+    pub(crate) fn insert(&mut self, position: usize) {
+        self.counts.insert(position, 0);
+        // The first count of every later chunk was the last one of the chunk before.
+        let chunk = position / CHUNK;
+        for next in chunk + 1..self.counts.len().div_ceil(CHUNK) {
+            let moved = self.counts[next * CHUNK] as usize;
+            self.chunks[next - 1] -= moved;
+            match self.chunks.get_mut(next) {
+                Some(sum) => *sum += moved,
+                None => self.chunks.push(moved),
+            }
+        }
+        if chunk == self.chunks.len() {
+            self.chunks.push(0);
+        }
+        self.rebuild_from(chunk);
+    }
+
+    /// Removes the count at `position`, shifting the later ones.
+    pub(crate) fn remove(&mut self, position: usize) {
+        let chunk = position / CHUNK;
+        self.chunks[chunk] -= self.counts.remove(position) as usize;
+        // The last count of every chunk from `chunk` on was the first one of the chunk after.
+        for next in chunk + 1..self.chunks.len() {
+            let moved = self.counts[next * CHUNK - 1] as usize;
+            self.chunks[next] -= moved;
+            self.chunks[next - 1] += moved;
+        }
+        self.chunks.truncate(self.counts.len().div_ceil(CHUNK));
+        self.rebuild_from(chunk);
+    }
+
+    pub(crate) fn get(&self, position: usize) -> usize {
+        self.counts[position] as usize
+    }
+
+    pub(crate) fn add(&mut self, position: usize, count: usize) {
+        self.counts[position] += count as u32;
+        self.chunks[position / CHUNK] += count;
+        let mut i = position / CHUNK + 1;
+        while let Some(sum) = self.sums.get_mut(i - 1) {
+            *sum += count;
+            i += lowest_bit(i);
+        }
+    }
+
+    pub(crate) fn sub(&mut self, position: usize, count: usize) {
+        self.counts[position] -= count as u32;
+        self.chunks[position / CHUNK] -= count;
+        let mut i = position / CHUNK + 1;
+        while let Some(sum) = self.sums.get_mut(i - 1) {
+            *sum -= count;
+            i += lowest_bit(i);
+        }
+    }
+
+    /// Returns the sum of the counts before `position`.
+    pub(crate) fn prefix(&self, position: usize) -> usize {
+        let chunk = position / CHUNK;
+        let mut sum = self.sum_chunk(chunk * CHUNK..position);
+        let mut i = chunk;
+        while i > 0 {
+            sum += self.sums[i - 1];
+            i -= lowest_bit(i);
+        }
+        sum
+    }
+
+    /// Returns the position of the count that the `n`-th unit falls into, counting from zero, and
+    /// the index of the unit within that count. `n` has to be less than the sum of all counts.
+    pub(crate) fn select(&self, mut n: usize) -> (usize, usize) {
+        let mut chunk = 0;
+        let mut step = (self.sums.len() + 1).next_power_of_two() / 2;
+        while step > 0 {
+            if chunk + step <= self.sums.len() && self.sums[chunk + step - 1] <= n {
+                chunk += step;
+                n -= self.sums[chunk - 1];
+            }
+            step /= 2;
+        }
+        let mut position = chunk * CHUNK;
+        while n >= self.counts[position] as usize {
+            n -= self.counts[position] as usize;
+            position += 1;
+        }
+        (position, n)
+    }
+
+    fn sum_chunk(&self, range: Range<usize>) -> usize {
+        self.counts[range].iter().map(|&count| count as usize).sum()
+    }
+
+    /// Rebuilds the tree from `chunk` on, given that the entries before are intact.
+    fn rebuild_from(&mut self, chunk: usize) {
+        let len = self.chunks.len();
+        self.sums.truncate(chunk);
+        self.sums.extend_from_slice(&self.chunks[chunk..]);
+        // The entries before `chunk` whose parents are not.
+        let mut i = chunk;
+        while i > 0 {
+            let parent = i + lowest_bit(i);
+            if parent <= len {
+                self.sums[parent - 1] += self.sums[i - 1];
+            }
+            i -= lowest_bit(i);
+        }
+        for i in chunk + 1..=len {
+            let parent = i + lowest_bit(i);
+            if parent <= len {
+                self.sums[parent - 1] += self.sums[i - 1];
+            }
+        }
+    }
+}
+
+fn lowest_bit(i: usize) -> usize {
+    i & i.wrapping_neg()
+}
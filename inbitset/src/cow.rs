@@ -0,0 +1,418 @@
+use std::{iter::FusedIterator, sync::Arc};
+
+use crate::{BitSetView, Bits, Change, Diff, LevelIndices, bits::SCALAR_BITS};
+
+/// Bitset whose clones share their blocks until they are modified.
+///
+/// Uses the same three-level hierarchy as [`BitSet`](crate::BitSet), but instead of pools the
+/// middle and bottom blocks are reference counted, and every top block refers to its children
+/// through one shared array. Cloning copies only the top level, and a modification copies the
+/// blocks on the path to the changed bit that are still shared with another clone, leaving the
+/// rest shared. Keeping a few snapshots of a set that changes a little between them therefore
+/// costs about as much as the changed blocks.
+///
+/// Emptied blocks are dropped right away, so the occupancy bits are always exact.
+#[derive(Clone)]
+pub struct CowBitSet<const LANES: usize = 4> {
+    /// Sorted by key.
+    tops: Vec<TopBlock<LANES>>,
+    len: usize,
+}
+
+/// Children of a top or middle block, indexed by the bit that stands for them.
+type Children<T, const LANES: usize> = [[Option<Arc<T>>; SCALAR_BITS]; LANES];
+
+#[derive(Clone, PartialEq, Eq)]
+struct TopBlock<const LANES: usize> {
+    key: usize,
+    bits: Bits<LANES>,
+    children: Arc<Children<MiddleBlock<LANES>, LANES>>,
+}
+
+#[derive(Clone, PartialEq, Eq)]
+struct MiddleBlock<const LANES: usize> {
+    bits: Bits<LANES>,
+    children: Children<Bits<LANES>, LANES>,
+}
+
+impl<const LANES: usize> Default for MiddleBlock<LANES> {
+    fn default() -> Self {
+        Self {
+            bits: Bits::default(),
+            children: empty_children(),
+        }
+    }
+}
+
+fn empty_children<T, const LANES: usize>() -> Children<T, LANES> {
+    std::array::from_fn(|_| std::array::from_fn(|_| None))
+}
+
+impl<const LANES: usize> CowBitSet<LANES> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the bit at `bit_index`. Returns true if it was not set before. Only copies shared
+    /// blocks if the bit was not set.
+    pub fn insert(&mut self, bit_index: usize) -> bool {
+        if self.contains(bit_index) {
+            return false;
+        }
+        let indices = LevelIndices::<LANES>::new(bit_index);
+        let position = match self.top_position(indices.key) {
+            Ok(position) => position,
+            Err(position) => {
+                let top_block = TopBlock {
+                    key: indices.key,
+                    bits: Bits::default(),
+                    children: Arc::new(empty_children()),
+                };
+                self.tops.insert(position, top_block);
+                position
+            }
+        };
+
+        let top_block = &mut self.tops[position];
+        let middle_slot =
+            &mut Arc::make_mut(&mut top_block.children).as_flattened_mut()[indices.top];
+        let middle_block = Arc::make_mut(middle_slot.get_or_insert_default());
+        let bottom_slot = &mut middle_block.children.as_flattened_mut()[indices.middle];
+        let bottom_block = Arc::make_mut(bottom_slot.get_or_insert_default());
+        unsafe {
+            bottom_block.set_unchecked(indices.bottom);
+            middle_block.bits.set_unchecked(indices.middle);
+            top_block.bits.set_unchecked(indices.top);
+        }
+        self.len += 1;
+        true
+    }
+
+    /// Unsets the bit at `bit_index`. Returns true if it was set. Only copies shared blocks if the
+    /// bit was set.
+    pub fn remove(&mut self, bit_index: usize) -> bool {
+        if !self.contains(bit_index) {
+            return false;
+        }
+        let indices = LevelIndices::<LANES>::new(bit_index);
+        let Ok(position) = self.top_position(indices.key) else {
+            unreachable!("the bit is set");
+        };
+
+        let top_block = &mut self.tops[position];
+        let middle_slot =
+            &mut Arc::make_mut(&mut top_block.children).as_flattened_mut()[indices.top];
+        let Some(middle_block) = middle_slot.as_mut().map(Arc::make_mut) else {
+            unreachable!("the bit is set");
+        };
+        let bottom_slot = &mut middle_block.children.as_flattened_mut()[indices.middle];
+        let Some(bottom_block) = bottom_slot.as_mut().map(Arc::make_mut) else {
+            unreachable!("the bit is set");
+        };
+        unsafe {
+            bottom_block.unset_unchecked(indices.bottom);
+            if bottom_block.is_empty() {
+                *bottom_slot = None;
+                middle_block.bits.unset_unchecked(indices.middle);
+                if middle_block.bits.is_empty() {
+                    *middle_slot = None;
+                    top_block.bits.unset_unchecked(indices.top);
+                    if top_block.bits.is_empty() {
+                        self.tops.remove(position);
+                    }
+                }
+            }
+        }
+        self.len -= 1;
+        true
+    }
+
+    pub fn contains(&self, bit_index: usize) -> bool {
+        let indices = LevelIndices::<LANES>::new(bit_index);
+        self.bottom_block(indices.key, indices.top, indices.middle)
+            .is_some_and(|bits| unsafe { bits.get_unchecked(indices.bottom) })
+    }
+
+    /// Returns the number of set bits.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if no bits are set.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Unsets all bits, releasing this set's references to its blocks.
+    pub fn clear(&mut self) {
+        self.tops.clear();
+        self.len = 0;
+    }
+
+    /// Returns the number of middle and bottom blocks this set shares with `other`, that is the
+    /// blocks neither has copied since one was cloned from the other.
+    pub fn shared_blocks(&self, other: &Self) -> usize {
+        let subtree = |middle_block: &MiddleBlock<LANES>| 1 + middle_block.bits.count_set();
+        let mut shared = 0;
+        for top_block in &self.tops {
+            let Some(other) = other.top_block(top_block.key) else {
+                continue;
+            };
+            let pairs = top_block.children.as_flattened().iter();
+            let pairs = pairs.zip(other.children.as_flattened()).flat_map(both);
+            if Arc::ptr_eq(&top_block.children, &other.children) {
+                shared += pairs
+                    .map(|(middle_block, _)| subtree(middle_block))
+                    .sum::<usize>();
+                continue;
+            }
+            for (middle_block, other) in pairs {
+                if Arc::ptr_eq(middle_block, other) {
+                    shared += subtree(middle_block);
+                    continue;
+                }
+                let pairs = middle_block.children.as_flattened().iter();
+                let pairs = pairs.zip(other.children.as_flattened()).flat_map(both);
+                shared += pairs.filter(|(a, b)| Arc::ptr_eq(a, b)).count();
+            }
+        }
+        shared
+    }
+
+    /// Returns an iterator over the changes from this set to `new`, like [`diff`](crate::diff),
+    /// but skipping the blocks both sets share. Diffing a snapshot against a later clone of it
+    /// therefore only visits the blocks that were copied since.
+    pub fn diff<'a>(&'a self, new: &'a Self) -> CowDiff<'a, LANES> {
+        let old_side = Unshared {
+            set: self,
+            other: new,
+        };
+        let new_side = Unshared {
+            set: new,
+            other: self,
+        };
+        CowDiff(Diff::new(old_side, new_side))
+    }
+
+    /// Returns the position of the top block with `key` in `tops`, or where it would be inserted.
+    fn top_position(&self, key: usize) -> Result<usize, usize> {
+        self.tops
+            .binary_search_by_key(&key, |top_block| top_block.key)
+    }
+
+    fn top_block(&self, key: usize) -> Option<&TopBlock<LANES>> {
+        let position = self.top_position(key).ok()?;
+        Some(&self.tops[position])
+    }
+
+    fn middle_block(&self, key: usize, top: usize) -> Option<&MiddleBlock<LANES>> {
+        let top_block = self.top_block(key)?;
+        top_block.children.as_flattened()[top].as_deref()
+    }
+
+    /// Returns the middle block under the `top` bit of the top block at `position` in `tops`.
+    fn middle_at(&self, (position, top): (usize, usize)) -> Option<&MiddleBlock<LANES>> {
+        self.tops[position].children.as_flattened()[top].as_deref()
+    }
+
+    fn bottom_block(&self, key: usize, top: usize, middle: usize) -> Option<&Bits<LANES>> {
+        let middle_block = self.middle_block(key, top)?;
+        middle_block.children.as_flattened()[middle].as_deref()
+    }
+}
+
+impl<const LANES: usize> Default for CowBitSet<LANES> {
+    fn default() -> Self {
+        Self {
+            tops: Vec::new(),
+            len: 0,
+        }
+    }
+}
+
+/// Compares the set bits. Blocks shared by both sets compare equal without looking at them, as
+/// `Arc` does for types that implement `Eq`.
+impl<const LANES: usize> PartialEq for CowBitSet<LANES> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.tops == other.tops
+    }
+}
+
+impl<const LANES: usize> Eq for CowBitSet<LANES> {}
+
+/// Handles are the position of the top block in `tops`, and that with the bit of the middle block.
+impl<const LANES: usize> BitSetView<LANES> for CowBitSet<LANES> {
+    type Top = Option<usize>;
+    type Middle = Option<(usize, usize)>;
+
+    fn next_top(&self, key: usize) -> Option<usize> {
+        let position = self.tops.partition_point(|top_block| top_block.key < key);
+        self.tops.get(position).map(|top_block| top_block.key)
+    }
+
+    fn prev_top(&self, key: usize) -> Option<usize> {
+        let position = self.tops.partition_point(|top_block| top_block.key <= key);
+        Some(self.tops.get(position.checked_sub(1)?)?.key)
+    }
+
+    fn enter_top(&self, key: usize) -> (Self::Top, Bits<LANES>) {
+        match self.top_position(key) {
+            Ok(position) => (Some(position), self.tops[position].bits),
+            Err(_) => (None, Bits::default()),
+        }
+    }
+
+    fn enter_middle(&self, parent: &Self::Top, top: usize) -> (Self::Middle, Bits<LANES>) {
+        let handle = parent.map(|position| (position, top));
+        match handle.and_then(|handle| self.middle_at(handle)) {
+            Some(middle_block) => (handle, middle_block.bits),
+            None => (None, Bits::default()),
+        }
+    }
+
+    fn enter_bottom(&self, parent: &Self::Middle, middle: usize) -> Bits<LANES> {
+        let Some(middle_block) = parent.and_then(|handle| self.middle_at(handle)) else {
+            return Bits::default();
+        };
+        middle_block.children.as_flattened()[middle]
+            .as_deref()
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn contains(&self, bit_index: usize) -> bool {
+        CowBitSet::contains(self, bit_index)
+    }
+}
+
+/// Iterator over the changes between two [`CowBitSet`]s, see [`CowBitSet::diff`].
+#[derive(Clone)]
+pub struct CowDiff<'a, const LANES: usize = 4>(
+    Diff<Unshared<'a, LANES>, Unshared<'a, LANES>, LANES>,
+);
+
+impl<const LANES: usize> Iterator for CowDiff<'_, LANES> {
+    type Item = Change;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+}
+
+impl<const LANES: usize> FusedIterator for CowDiff<'_, LANES> {}
+
+/// One side of [`CowBitSet::diff`], the bits of `set` without the blocks it shares with `other`.
+/// Shared blocks are left out of both sides, so the walk never descends into them.
+#[derive(Copy, Clone)]
+struct Unshared<'a, const LANES: usize> {
+    set: &'a CowBitSet<LANES>,
+    other: &'a CowBitSet<LANES>,
+}
+
+impl<const LANES: usize> Unshared<'_, LANES> {
+    fn is_shared(&self, top_block: &TopBlock<LANES>) -> bool {
+        self.other
+            .top_block(top_block.key)
+            .is_some_and(|other| Arc::ptr_eq(&top_block.children, &other.children))
+    }
+}
+
+/// Returns both children, if both exist.
+fn both<'a, T>(
+    (a, b): (&'a Option<Arc<T>>, &'a Option<Arc<T>>),
+) -> Option<(&'a Arc<T>, &'a Arc<T>)> {
+    Some((a.as_ref()?, b.as_ref()?))
+}
+
+/// Returns `bits` without the children that are the same block in `children` and `other`.
+fn unshared_bits<T, const LANES: usize>(
+    mut bits: Bits<LANES>,
+    children: &Children<T, LANES>,
+    other: &Children<T, LANES>,
+) -> Bits<LANES> {
+    let pairs = children.as_flattened().iter().zip(other.as_flattened());
+    for (index, pair) in pairs.map(both).enumerate() {
+        if pair.is_some_and(|(child, other)| Arc::ptr_eq(child, other)) {
+            unsafe { bits.unset_unchecked(index) };
+        }
+    }
+    bits
+}
+
+/// Handles are the handles of both sets.
+impl<const LANES: usize> BitSetView<LANES> for Unshared<'_, LANES> {
+    type Top = (Option<usize>, Option<usize>);
+    type Middle = (Option<(usize, usize)>, Option<(usize, usize)>);
+
+    fn next_top(&self, key: usize) -> Option<usize> {
+        let tops = &self.set.tops;
+        let position = tops.partition_point(|top_block| top_block.key < key);
+        let top_block = tops[position..]
+            .iter()
+            .find(|top_block| !self.is_shared(top_block))?;
+        Some(top_block.key)
+    }
+
+    fn prev_top(&self, key: usize) -> Option<usize> {
+        let tops = &self.set.tops;
+        let position = tops.partition_point(|top_block| top_block.key <= key);
+        let top_block = tops[..position]
+            .iter()
+            .rfind(|top_block| !self.is_shared(top_block))?;
+        Some(top_block.key)
+    }
+
+    fn enter_top(&self, key: usize) -> (Self::Top, Bits<LANES>) {
+        let (top, bits) = self.set.enter_top(key);
+        let (other_top, _) = self.other.enter_top(key);
+        let bits = match (top, other_top) {
+            (Some(top), Some(other_top)) => unshared_bits(
+                bits,
+                &self.set.tops[top].children,
+                &self.other.tops[other_top].children,
+            ),
+            _ => bits,
+        };
+        ((top, other_top), bits)
+    }
+
+    fn enter_middle(
+        &self,
+        (top, other_top): &Self::Top,
+        index: usize,
+    ) -> (Self::Middle, Bits<LANES>) {
+        let (middle, bits) = self.set.enter_middle(top, index);
+        let (other_middle, _) = self.other.enter_middle(other_top, index);
+        let middle_blocks = (
+            middle.and_then(|handle| self.set.middle_at(handle)),
+            other_middle.and_then(|handle| self.other.middle_at(handle)),
+        );
+        let bits = match middle_blocks {
+            (Some(middle_block), Some(other)) => {
+                unshared_bits(bits, &middle_block.children, &other.children)
+            }
+            _ => bits,
+        };
+        ((middle, other_middle), bits)
+    }
+
+    fn enter_bottom(&self, (middle, _): &Self::Middle, index: usize) -> Bits<LANES> {
+        self.set.enter_bottom(middle, index)
+    }
+}
+
+impl<const LANES: usize> Extend<usize> for CowBitSet<LANES> {
+    fn extend<T: IntoIterator<Item = usize>>(&mut self, iter: T) {
+        for bit_index in iter {
+            self.insert(bit_index);
+        }
+    }
+}
+
+impl<const LANES: usize> FromIterator<usize> for CowBitSet<LANES> {
+    fn from_iter<T: IntoIterator<Item = usize>>(iter: T) -> Self {
+        let mut set = Self::default();
+        set.extend(iter);
+        set
+    }
+}
@@ -0,0 +1,143 @@
+use std::iter::FusedIterator;
+
+use crate::{
+    BitSetView, Bits, LevelIndices,
+    ops::{Op, Xor},
+};
+
+/// A bit that differs between two sets, see [`diff`].
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Change {
+    /// Set in the new set only.
+    Added(usize),
+    /// Set in the old set only.
+    Removed(usize),
+}
+
+impl Change {
+    /// Returns the index of the changed bit.
+    pub fn index(self) -> usize {
+        match self {
+            Change::Added(index) | Change::Removed(index) => index,
+        }
+    }
+}
+
+/// Iterator over the changes from one set to another, in ascending order of their index, see
+/// [`diff`].
+#[derive(Clone)]
+pub struct Diff<A, B, const LANES: usize = 4>
+where
+    A: BitSetView<LANES>,
+    B: BitSetView<LANES>,
+{
+    old: A,
+    new: B,
+    next_key: Option<usize>,
+    key: usize,
+    /// The handles of the current top block in both sets.
+    top_blocks: (A::Top, B::Top),
+    top: Bits<LANES>,
+    top_index: usize,
+    /// The handles of the current middle block in both sets.
+    middle_blocks: (A::Middle, B::Middle),
+    middle: Bits<LANES>,
+    middle_index: usize,
+    /// The not yet visited bits of the current bottom block that differ.
+    changed: Bits<LANES>,
+    /// The bits of the current bottom block that are set in the new set only.
+    added: Bits<LANES>,
+}
+
+impl<A, B, const LANES: usize> Diff<A, B, LANES>
+where
+    A: BitSetView<LANES>,
+    B: BitSetView<LANES>,
+{
+    pub fn new(old: A, new: B) -> Self {
+        Self {
+            old,
+            new,
+            next_key: Some(0),
+            key: 0,
+            top_blocks: Default::default(),
+            top: Bits::default(),
+            top_index: 0,
+            middle_blocks: Default::default(),
+            middle: Bits::default(),
+            middle_index: 0,
+            changed: Bits::default(),
+            added: Bits::default(),
+        }
+    }
+}
+
+impl<A, B, const LANES: usize> Iterator for Diff<A, B, LANES>
+where
+    A: BitSetView<LANES>,
+    B: BitSetView<LANES>,
+{
+    type Item = Change;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(bit) = self.changed.pop_first_set() {
+                let base =
+                    LevelIndices::<LANES>::bottom_base(self.key, self.top_index, self.middle_index);
+                if unsafe { self.added.get_unchecked(bit) } {
+                    return Some(Change::Added(base + bit));
+                }
+                return Some(Change::Removed(base + bit));
+            }
+            if let Some(bit) = self.middle.pop_first_set() {
+                self.middle_index = bit;
+                let old = self.old.enter_bottom(&self.middle_blocks.0, bit);
+                let new = self.new.enter_bottom(&self.middle_blocks.1, bit);
+                // Blocks that are the same on both sides leave nothing to visit.
+                self.changed = old ^ new;
+                self.added = new & self.changed;
+                continue;
+            }
+            if let Some(bit) = self.top.pop_first_set() {
+                self.top_index = bit;
+                let (old_block, old) = self.old.enter_middle(&self.top_blocks.0, bit);
+                let (new_block, new) = self.new.enter_middle(&self.top_blocks.1, bit);
+                self.middle_blocks = (old_block, new_block);
+                self.middle = Xor.hierarchy(old, new);
+                continue;
+            }
+            let next_key = self.next_key?;
+            self.key = Xor.next_top(self.old.next_top(next_key), self.new.next_top(next_key))?;
+            self.next_key = self.key.checked_add(1);
+            let (old_block, old) = self.old.enter_top(self.key);
+            let (new_block, new) = self.new.enter_top(self.key);
+            self.top_blocks = (old_block, new_block);
+            self.top = Xor.hierarchy(old, new);
+        }
+    }
+}
+
+impl<A, B, const LANES: usize> FusedIterator for Diff<A, B, LANES>
+where
+    A: BitSetView<LANES>,
+    B: BitSetView<LANES>,
+{
+}
+
+/// Returns an iterator over the bits that differ between `old` and `new`, telling whether each was
+/// added or removed.
+///
+/// Both sets are walked together a whole block at a time, and the bottom blocks are compared with
+/// a single XOR, so only the blocks that actually changed yield anything. This is the same walk as
+/// [`BitSetView::xor`], only keeping track of which side each bit came from.
+///
+/// Blocks present in both sets are always visited, even if they are equal, so the cost grows with
+/// the number of blocks rather than the number of changes. To skip the blocks two copy-on-write
+/// sets share, use [`CowBitSet::diff`](crate::CowBitSet::diff).
+pub fn diff<A, B, const LANES: usize>(old: A, new: B) -> Diff<A, B, LANES>
+where
+    A: BitSetView<LANES>,
+    B: BitSetView<LANES>,
+{
+    Diff::new(old, new)
+}
@@ -0,0 +1,447 @@
+//! Compact binary encoding of a [`BitSet`], and [`BitSetRef`] to query it in place.
+//!
+//! The encoding stores the blocks of each level in breadth-first order, so the children of a block
+//! are consecutive and a block only has to store the position of its first child. The child under
+//! a set bit is then found by counting the set bits below it. Free blocks are not stored, so equal
+//! sets always have the same encoding.
+//!
+//! All integers are little-endian, and bits are stored as `LANES` `u64` lanes, lowest bits first.
+//!
+//! | Field    | Size                        | Contents                                  |
+//! |----------|-----------------------------|-------------------------------------------|
+//! | magic    | 4                           | `b"inbs"`                                 |
+//! | version  | 1                           | `1`                                       |
+//! | lanes    | 1                           | `LANES`, the block geometry               |
+//! | reserved | 2                           | zeros                                     |
+//! | len      | 8                           | number of set bits                        |
+//! | tops     | 8                           | number of top blocks                      |
+//! | middles  | 8                           | number of middle blocks                   |
+//! | bottoms  | 8                           | number of bottom blocks                   |
+//! | top      | `tops * (16 + 8 * LANES)`   | key, first middle block, bits; by key     |
+//! | middle   | `middles * (8 + 8 * LANES)` | first bottom block, bits                  |
+//! | bottom   | `bottoms * 8 * LANES`       | bits                                      |
+//!
+//! Blocks are never empty, keys only cover indices up to `usize::MAX`, and the first child of each
+//! block follows the last child of the block before it, starting at zero. [`BitSetRef::new`]
+//! checks all of that, and that `len` matches the bottom blocks, so a validated encoding can be
+//! queried without further checks.
+
+use std::{error::Error, fmt};
+
+use crate::{BitSet, BitSetView, Bits, Config, LevelIndices, bits::Scalar};
+
+const MAGIC: [u8; 4] = *b"inbs";
+const VERSION: u8 = 1;
+const HEADER_SIZE: usize = 40;
+const LANE_SIZE: usize = size_of::<Scalar>();
+
+/// Error returned when bytes are not a valid encoding of a bitset.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum DecodeError {
+    /// The bytes do not start with the magic number.
+    Magic,
+    /// The encoding version is not supported.
+    Version(u8),
+    /// The encoding uses a different block geometry.
+    Lanes(u8),
+    /// The number of bytes does not match the header.
+    Length,
+    /// The blocks are not laid out as documented.
+    Corrupt,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Magic => write!(f, "not an encoded bitset"),
+            Self::Version(version) => write!(f, "unsupported encoding version {version}"),
+            Self::Lanes(lanes) => write!(f, "encoded with {lanes} lanes per block"),
+            Self::Length => write!(f, "length does not match the header"),
+            Self::Corrupt => write!(f, "corrupt block layout"),
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+impl<C: Config, const LANES: usize> BitSet<C, LANES> {
+    /// Encodes the set as described in [`crate::encoding`].
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let middle_blocks = self.top_blocks().flat_map(|(_, top_block)| {
+            let mut top_bits = top_block.bits;
+            std::iter::from_fn(move || {
+                let top = top_bits.pop_first_set()?;
+                Some(unsafe {
+                    self.middle
+                        .blocks
+                        .get_unchecked(top_block.child_unchecked(top))
+                })
+            })
+        });
+        let middles = middle_blocks.clone().count();
+        let bottoms = middle_blocks
+            .clone()
+            .map(|middle_block| middle_block.bits.count_set())
+            .sum();
+        let mut bytes = Vec::with_capacity(
+            HEADER_SIZE
+                + self.tops.len() * Layout::<LANES>::TOP_SIZE
+                + middles * Layout::<LANES>::MIDDLE_SIZE
+                + bottoms * Layout::<LANES>::BOTTOM_SIZE,
+        );
+        bytes.extend(MAGIC);
+        bytes.extend([VERSION, LANES as u8, 0, 0]);
+        for count in [self.len, self.tops.len(), middles, bottoms] {
+            bytes.extend((count as u64).to_le_bytes());
+        }
+
+        let write_bits = |bytes: &mut Vec<u8>, bits: Bits<LANES>| {
+            for lane in bits.to_lanes() {
+                bytes.extend(lane.to_le_bytes());
+            }
+        };
+        let mut first_child = 0;
+        for (key, top_block) in self.top_blocks() {
+            bytes.extend((key as u64).to_le_bytes());
+            bytes.extend((first_child as u64).to_le_bytes());
+            write_bits(&mut bytes, top_block.bits);
+            first_child += top_block.bits.count_set();
+        }
+        let mut first_child = 0;
+        for middle_block in middle_blocks.clone() {
+            bytes.extend((first_child as u64).to_le_bytes());
+            write_bits(&mut bytes, middle_block.bits);
+            first_child += middle_block.bits.count_set();
+        }
+        for middle_block in middle_blocks {
+            middle_block.bits.for_each_set(|middle| unsafe {
+                let bottom_block = middle_block.child_unchecked(middle);
+                write_bits(
+                    &mut bytes,
+                    self.bottom.blocks.get_unchecked(bottom_block).bits,
+                );
+            });
+        }
+        bytes
+    }
+
+    /// Decodes a set encoded by [`Self::to_bytes`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let set_ref = BitSetRef::<LANES>::new(bytes)?;
+        let mut set = Self::default();
+        for top_position in 0..set_ref.tops {
+            let (key, first_middle, top_bits) = set_ref.top_block(top_position);
+            let mut middle_position = first_middle;
+            top_bits.for_each_set(|top| {
+                let (first_bottom, middle_bits) = set_ref.middle_block(middle_position);
+                let mut bottom_position = first_bottom;
+                middle_bits.for_each_set(|middle| {
+                    let bits = set_ref.bottom_block(bottom_position);
+                    unsafe { set.insert_bits(key, top, middle, bits) };
+                    bottom_position += 1;
+                });
+                middle_position += 1;
+            });
+        }
+        Ok(set)
+    }
+}
+
+/// Read-only set borrowed from its encoding, see [`crate::encoding`].
+///
+/// Implements [`BitSetView`], so it can be iterated and combined with other sets without decoding
+/// it first. Looking up a top block is a binary search over the keys, the blocks below it are
+/// found in constant time.
+#[derive(Copy, Clone, Debug)]
+pub struct BitSetRef<'a, const LANES: usize = 4> {
+    bytes: &'a [u8],
+    len: usize,
+    tops: usize,
+    middles: usize,
+}
+
+impl<'a, const LANES: usize> BitSetRef<'a, LANES> {
+    /// Validates `bytes` as an encoded set with `LANES` lanes per block.
+    pub fn new(bytes: &'a [u8]) -> Result<Self, DecodeError> {
+        let header = bytes.get(..HEADER_SIZE).ok_or(DecodeError::Length)?;
+        if header[..4] != MAGIC {
+            return Err(DecodeError::Magic);
+        }
+        if header[4] != VERSION {
+            return Err(DecodeError::Version(header[4]));
+        }
+        if header[5] as usize != LANES {
+            return Err(DecodeError::Lanes(header[5]));
+        }
+        if header[6..8] != [0, 0] {
+            return Err(DecodeError::Corrupt);
+        }
+        let count = |index: usize| {
+            let count = read_u64(header, 8 + index * 8);
+            usize::try_from(count).map_err(|_| DecodeError::Length)
+        };
+        let (len, tops, middles, bottoms) = (count(0)?, count(1)?, count(2)?, count(3)?);
+        let size = [
+            (tops, Layout::<LANES>::TOP_SIZE),
+            (middles, Layout::<LANES>::MIDDLE_SIZE),
+            (bottoms, Layout::<LANES>::BOTTOM_SIZE),
+        ]
+        .into_iter()
+        .try_fold(HEADER_SIZE, |size, (count, block_size)| {
+            size.checked_add(count.checked_mul(block_size)?)
+        });
+        if size != Some(bytes.len()) {
+            return Err(DecodeError::Length);
+        }
+
+        let set_ref = Self {
+            bytes,
+            len,
+            tops,
+            middles,
+        };
+        set_ref.validate(bottoms)?;
+        Ok(set_ref)
+    }
+
+    /// Returns the number of set bits.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if no bits are set.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the encoding the set is borrowed from.
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    fn validate(&self, bottoms: usize) -> Result<(), DecodeError> {
+        let corrupt = |valid: bool| {
+            if valid {
+                Ok(())
+            } else {
+                Err(DecodeError::Corrupt)
+            }
+        };
+
+        // Fields that do not fit `usize` would be truncated by the accessors below.
+        let field = |offset: usize| {
+            usize::try_from(read_u64(self.bytes, offset)).map_err(|_| DecodeError::Corrupt)
+        };
+
+        let max_key = usize::MAX / LevelIndices::<LANES>::TOP_BLOCK_CAP;
+        let mut prev_key = None;
+        let mut next_child = 0;
+        for top_position in 0..self.tops {
+            let offset = HEADER_SIZE + top_position * Layout::<LANES>::TOP_SIZE;
+            let (key, first_child) = (field(offset)?, field(offset + 8)?);
+            let bits = self.top_block(top_position).2;
+            corrupt(prev_key < Some(key) && key <= max_key)?;
+            corrupt(first_child == next_child && !bits.is_empty())?;
+            prev_key = Some(key);
+            next_child += bits.count_set();
+        }
+        corrupt(next_child == self.middles)?;
+
+        let mut next_child = 0;
+        for middle_position in 0..self.middles {
+            let offset = self.middle_offset() + middle_position * Layout::<LANES>::MIDDLE_SIZE;
+            let first_child = field(offset)?;
+            let bits = self.middle_block(middle_position).1;
+            corrupt(first_child == next_child && !bits.is_empty())?;
+            next_child += bits.count_set();
+        }
+        corrupt(next_child == bottoms)?;
+
+        let mut len = 0;
+        for bottom_position in 0..bottoms {
+            let bits = self.bottom_block(bottom_position);
+            corrupt(!bits.is_empty())?;
+            len += bits.count_set();
+        }
+        corrupt(len == self.len)
+    }
+
+    /// Returns the key, the position of the first child and the bits of a top block.
+    fn top_block(&self, position: usize) -> (usize, usize, Bits<LANES>) {
+        let offset = HEADER_SIZE + position * Layout::<LANES>::TOP_SIZE;
+        (
+            read_u64(self.bytes, offset) as usize,
+            read_u64(self.bytes, offset + 8) as usize,
+            read_bits(self.bytes, offset + 16),
+        )
+    }
+
+    /// Returns the position of the first child and the bits of a middle block.
+    fn middle_block(&self, position: usize) -> (usize, Bits<LANES>) {
+        let offset = self.middle_offset() + position * Layout::<LANES>::MIDDLE_SIZE;
+        (
+            read_u64(self.bytes, offset) as usize,
+            read_bits(self.bytes, offset + 8),
+        )
+    }
+
+    fn bottom_block(&self, position: usize) -> Bits<LANES> {
+        let offset = self.middle_offset()
+            + self.middles * Layout::<LANES>::MIDDLE_SIZE
+            + position * Layout::<LANES>::BOTTOM_SIZE;
+        read_bits(self.bytes, offset)
+    }
+
+    fn middle_offset(&self) -> usize {
+        HEADER_SIZE + self.tops * Layout::<LANES>::TOP_SIZE
+    }
+
+    /// Returns the position of the top block with `key`, if there is any.
+    fn top_position(&self, key: usize) -> Option<usize> {
+        let position = self.top_partition_point(key);
+        (position < self.tops && self.top_block(position).0 == key).then_some(position)
+    }
+
+    /// Returns the number of top blocks with keys less than `key`.
+    fn top_partition_point(&self, key: usize) -> usize {
+        let (mut low, mut high) = (0, self.tops);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if self.top_block(mid).0 < key {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        low
+    }
+}
+
+/// Handles are the positions of the blocks in their sections.
+impl<const LANES: usize> BitSetView<LANES> for BitSetRef<'_, LANES> {
+    type Top = Option<usize>;
+    type Middle = Option<usize>;
+
+    fn next_top(&self, key: usize) -> Option<usize> {
+        let position = self.top_partition_point(key);
+        (position < self.tops).then(|| self.top_block(position).0)
+    }
+
+    fn prev_top(&self, key: usize) -> Option<usize> {
+        let position = match key.checked_add(1) {
+            Some(next) => self.top_partition_point(next),
+            None => self.tops,
+        };
+        Some(self.top_block(position.checked_sub(1)?).0)
+    }
+
+    fn enter_top(&self, key: usize) -> (Self::Top, Bits<LANES>) {
+        match self.top_position(key) {
+            Some(position) => (Some(position), self.top_block(position).2),
+            None => (None, Bits::default()),
+        }
+    }
+
+    fn enter_middle(&self, parent: &Self::Top, top: usize) -> (Self::Middle, Bits<LANES>) {
+        assert!(top < Bits::<LANES>::SIZE, "top index out of range");
+        let Some(top_position) = *parent else {
+            return (None, Bits::default());
+        };
+        let (_, first_child, bits) = self.top_block(top_position);
+        match child_position(first_child, bits, top) {
+            Some(position) => (Some(position), self.middle_block(position).1),
+            None => (None, Bits::default()),
+        }
+    }
+
+    fn enter_bottom(&self, parent: &Self::Middle, middle: usize) -> Bits<LANES> {
+        assert!(middle < Bits::<LANES>::SIZE, "middle index out of range");
+        let Some(middle_position) = *parent else {
+            return Bits::default();
+        };
+        let (first_child, bits) = self.middle_block(middle_position);
+        match child_position(first_child, bits, middle) {
+            Some(position) => self.bottom_block(position),
+            None => Bits::default(),
+        }
+    }
+}
+
+/// Sizes of the encoded blocks.
+struct Layout<const LANES: usize>;
+
+impl<const LANES: usize> Layout<LANES> {
+    const BOTTOM_SIZE: usize = LANES * LANE_SIZE;
+    const MIDDLE_SIZE: usize = 8 + Self::BOTTOM_SIZE;
+    const TOP_SIZE: usize = 16 + Self::BOTTOM_SIZE;
+}
+
+/// Returns the position of the child under `bit` of a block with `bits`, if the bit is set.
+fn child_position<const LANES: usize>(
+    first_child: usize,
+    bits: Bits<LANES>,
+    bit: usize,
+) -> Option<usize> {
+    let present = unsafe { bits.get_unchecked(bit) };
+    present.then(|| first_child + (bits & Bits::ones_below(bit)).count_set())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    let mut lane = [0; LANE_SIZE];
+    lane.copy_from_slice(&bytes[offset..offset + LANE_SIZE]);
+    u64::from_le_bytes(lane)
+}
+
+fn read_bits<const LANES: usize>(bytes: &[u8], offset: usize) -> Bits<LANES> {
+    Bits::from_lanes(std::array::from_fn(|lane| {
+        read_u64(bytes, offset + lane * LANE_SIZE)
+    }))
+}
+
+#[cfg(feature = "serde")]
+mod serde_impls {
+    use std::fmt;
+
+    use serde::{
+        Deserialize, Deserializer, Serialize, Serializer,
+        de::{self, SeqAccess, Visitor},
+    };
+
+    use crate::{BitSet, Config};
+
+    /// Serializes the set as bytes in the [`crate::encoding`] format.
+    impl<C: Config, const LANES: usize> Serialize for BitSet<C, LANES> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_bytes(&self.to_bytes())
+        }
+    }
+
+    impl<'de, C: Config, const LANES: usize> Deserialize<'de> for BitSet<C, LANES> {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            deserializer.deserialize_bytes(BytesVisitor::<C, LANES>(Default::default()))
+        }
+    }
+
+    struct BytesVisitor<C, const LANES: usize>(std::marker::PhantomData<C>);
+
+    impl<'de, C: Config, const LANES: usize> Visitor<'de> for BytesVisitor<C, LANES> {
+        type Value = BitSet<C, LANES>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "an encoded bitset")
+        }
+
+        fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<Self::Value, E> {
+            BitSet::from_bytes(bytes).map_err(E::custom)
+        }
+
+        // For formats without a native byte string type.
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+            while let Some(byte) = seq.next_element()? {
+                bytes.push(byte);
+            }
+            self.visit_bytes(&bytes)
+        }
+    }
+}
@@ -0,0 +1,356 @@
+use std::{iter::FusedIterator, ops::RangeBounds};
+
+use crate::{BitSet, BitSetView, Bits, Config, LevelIndices, inclusive_bounds};
+
+/// Iterator over the set bits of a [`BitSetView`], in ascending order, or descending from the back.
+///
+/// Keeps the not yet visited bits of one block per level at each end, so whole empty subtrees are
+/// skipped by looking only at the occupancy bits of their parents.
+#[derive(Clone)]
+pub struct Iter<S: BitSetView<LANES>, const LANES: usize = 4> {
+    set: S,
+    /// The first and last index that may still be yielded, or `None` once the ends have met.
+    range: Option<(usize, usize)>,
+    front: Option<Cursor<S::Top, S::Middle, LANES>>,
+    back: Option<Cursor<S::Top, S::Middle, LANES>>,
+}
+
+impl<S: BitSetView<LANES>, const LANES: usize> Iter<S, LANES> {
+    pub fn new(set: S) -> Self {
+        Self::with_range(set, ..)
+    }
+
+    /// Returns an iterator over the set bits in `range` only. The ends are found by descending
+    /// straight to the blocks containing them, without visiting the bits before or after.
+    pub fn with_range(set: S, range: impl RangeBounds<usize>) -> Self {
+        Self {
+            set,
+            range: inclusive_bounds(range),
+            front: None,
+            back: None,
+        }
+    }
+}
+
+impl<S: BitSetView<LANES>, const LANES: usize> Iterator for Iter<S, LANES> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (lo, hi) = self.range?;
+        let front = self
+            .front
+            .get_or_insert_with(|| Cursor::up_from(&self.set, lo));
+        match front.next_up(&self.set) {
+            Some(bit_index) if bit_index <= hi => {
+                self.range = (bit_index < hi).then(|| (bit_index + 1, hi));
+                Some(bit_index)
+            }
+            _ => {
+                self.range = None;
+                None
+            }
+        }
+    }
+
+    fn for_each<F>(self, mut f: F)
+    where
+        Self: Sized,
+        F: FnMut(Self::Item),
+    {
+        let Some((lo, hi)) = self.range else {
+            return;
+        };
+        if hi != usize::MAX {
+            // The front has to stop at the back, leave that to `next`.
+            for bit_index in self {
+                f(bit_index);
+            }
+            return;
+        }
+        let set = &self.set;
+        let cursor = self.front.unwrap_or_else(|| Cursor::up_from(set, lo));
+        let for_each_in_middle =
+            |f: &mut F, key: usize, top: usize, block: &S::Middle, middle: Bits<LANES>| {
+                middle.for_each_set(|middle| {
+                    let base = LevelIndices::<LANES>::bottom_base(key, top, middle);
+                    set.enter_bottom(block, middle)
+                        .for_each_set(|bit| f(base + bit));
+                });
+            };
+        let for_each_in_top = |f: &mut F, key: usize, block: &S::Top, top: Bits<LANES>| {
+            top.for_each_set(|top| {
+                let (middle_block, middle) = set.enter_middle(block, top);
+                for_each_in_middle(f, key, top, &middle_block, middle);
+            });
+        };
+
+        // Finish the blocks that are already partially visited, then walk the rest from the top.
+        cursor.bottom.for_each_set(|bit| f(cursor.base + bit));
+        for_each_in_middle(
+            &mut f,
+            cursor.key,
+            cursor.top_index,
+            &cursor.middle_block,
+            cursor.middle,
+        );
+        for_each_in_top(&mut f, cursor.key, &cursor.top_block, cursor.top);
+        let mut next_key = cursor.next_key;
+        while let Some(key) = next_key.and_then(|key| set.next_top(key)) {
+            let (top_block, top) = set.enter_top(key);
+            for_each_in_top(&mut f, key, &top_block, top);
+            next_key = key.checked_add(1);
+        }
+    }
+
+    /// Walks the blocks like [`Self::for_each`], rather than stepping with `next`, which is what
+    /// `sum`, `count` and most other consuming adapters end up calling.
+    fn fold<B, F>(self, init: B, mut f: F) -> B
+    where
+        Self: Sized,
+        F: FnMut(B, Self::Item) -> B,
+    {
+        // `for_each` takes a closure, so the accumulator is moved through an `Option`.
+        let mut acc = Some(init);
+        self.for_each(|bit_index| acc = acc.take().map(|acc| f(acc, bit_index)));
+        acc.expect("accumulator is put back after every call")
+    }
+}
+
+impl<S: BitSetView<LANES>, const LANES: usize> DoubleEndedIterator for Iter<S, LANES> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let (lo, hi) = self.range?;
+        let back = self
+            .back
+            .get_or_insert_with(|| Cursor::down_from(&self.set, hi));
+        match back.next_down(&self.set) {
+            Some(bit_index) if bit_index >= lo => {
+                self.range = (bit_index > lo).then(|| (lo, bit_index - 1));
+                Some(bit_index)
+            }
+            _ => {
+                self.range = None;
+                None
+            }
+        }
+    }
+
+    fn rfold<B, F>(self, init: B, mut f: F) -> B
+    where
+        Self: Sized,
+        F: FnMut(B, Self::Item) -> B,
+    {
+        let Some((lo, hi)) = self.range else {
+            return init;
+        };
+        if lo != 0 {
+            let mut iter = self;
+            let mut acc = init;
+            while let Some(bit_index) = iter.next_back() {
+                acc = f(acc, bit_index);
+            }
+            return acc;
+        }
+        let set = &self.set;
+        let cursor = self.back.unwrap_or_else(|| Cursor::down_from(set, hi));
+        // `for_each_set_rev` takes a closure, so the accumulator is moved through an `Option`.
+        let mut acc = Some(init);
+        let mut f = |bit_index| acc = acc.take().map(|acc| f(acc, bit_index));
+
+        // Finish the blocks that are already partially visited, then walk the rest from the top.
+        cursor.bottom.for_each_set_rev(|bit| f(cursor.base + bit));
+        let mut for_each_in_middle =
+            |key: usize, top: usize, block: &S::Middle, middle: Bits<LANES>| {
+                middle.for_each_set_rev(|middle| {
+                    let base = LevelIndices::<LANES>::bottom_base(key, top, middle);
+                    set.enter_bottom(block, middle)
+                        .for_each_set_rev(|bit| f(base + bit));
+                });
+            };
+        for_each_in_middle(
+            cursor.key,
+            cursor.top_index,
+            &cursor.middle_block,
+            cursor.middle,
+        );
+        let mut for_each_in_top = |key: usize, block: &S::Top, top: Bits<LANES>| {
+            top.for_each_set_rev(|top| {
+                let (middle_block, middle) = set.enter_middle(block, top);
+                for_each_in_middle(key, top, &middle_block, middle);
+            });
+        };
+        for_each_in_top(cursor.key, &cursor.top_block, cursor.top);
+        let mut next_key = cursor.next_key;
+        while let Some(key) = next_key.and_then(|key| set.prev_top(key)) {
+            let (top_block, top) = set.enter_top(key);
+            for_each_in_top(key, &top_block, top);
+            next_key = key.checked_sub(1);
+        }
+        acc.expect("accumulator is put back after every call")
+    }
+}
+
+impl<S: BitSetView<LANES>, const LANES: usize> FusedIterator for Iter<S, LANES> {}
+
+/// Draining iterator over the set bits of a [`BitSet`], in ascending order, see [`BitSet::drain`].
+///
+/// Reads the set as it is while iterating and clears it when dropped, so a leaked iterator leaves
+/// the set unchanged.
+pub struct Drain<'a, C: Config, const LANES: usize = 4> {
+    set: &'a mut BitSet<C, LANES>,
+    cursor: Cursor<Option<usize>, Option<usize>, LANES>,
+}
+
+impl<'a, C: Config, const LANES: usize> Drain<'a, C, LANES> {
+    pub(crate) fn new(set: &'a mut BitSet<C, LANES>) -> Self {
+        let cursor = Cursor::up_from(&*set, 0);
+        Self { set, cursor }
+    }
+}
+
+impl<C: Config, const LANES: usize> Iterator for Drain<'_, C, LANES> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.cursor.next_up(&*self.set)
+    }
+}
+
+impl<C: Config, const LANES: usize> FusedIterator for Drain<'_, C, LANES> {}
+
+impl<C: Config, const LANES: usize> Drop for Drain<'_, C, LANES> {
+    fn drop(&mut self) {
+        self.set.clear();
+    }
+}
+
+/// One end of an [`Iter`], the not yet visited bits of the current block at each level, with the
+/// handles of the current top and middle block to descend from.
+#[derive(Clone)]
+struct Cursor<T, M, const LANES: usize> {
+    /// The key of the next top block to look for, `None` past the end of the key space.
+    next_key: Option<usize>,
+    key: usize,
+    top_block: T,
+    top: Bits<LANES>,
+    top_index: usize,
+    middle_block: M,
+    middle: Bits<LANES>,
+    bottom: Bits<LANES>,
+    /// The index of the first bit of the current bottom block.
+    base: usize,
+}
+
+impl<T: Clone + Default, M: Clone + Default, const LANES: usize> Cursor<T, M, LANES> {
+    /// Returns a cursor for ascending iteration, positioned before `from`.
+    fn up_from<S>(set: &S, from: usize) -> Self
+    where
+        S: BitSetView<LANES, Top = T, Middle = M> + ?Sized,
+    {
+        let last = Bits::<LANES>::SIZE - 1;
+        let from = LevelIndices::<LANES>::new(from);
+        let (top_block, top) = set.enter_top(from.key);
+        let mut cursor = Self {
+            next_key: from.key.checked_add(1),
+            key: from.key,
+            top_block,
+            top: top & Bits::ones_between(from.top, last),
+            top_index: from.top,
+            middle_block: M::default(),
+            middle: Bits::default(),
+            bottom: Bits::default(),
+            base: LevelIndices::<LANES>::bottom_base(from.key, from.top, from.middle),
+        };
+        unsafe {
+            if cursor.top.unset_unchecked(from.top) {
+                let (middle_block, middle) = set.enter_middle(&cursor.top_block, from.top);
+                cursor.middle_block = middle_block;
+                cursor.middle = middle & Bits::ones_between(from.middle, last);
+                if cursor.middle.unset_unchecked(from.middle) {
+                    cursor.bottom = set.enter_bottom(&cursor.middle_block, from.middle)
+                        & Bits::ones_between(from.bottom, last);
+                }
+            }
+        }
+        cursor
+    }
+
+    /// Returns a cursor for descending iteration, positioned after `to`.
+    fn down_from<S>(set: &S, to: usize) -> Self
+    where
+        S: BitSetView<LANES, Top = T, Middle = M> + ?Sized,
+    {
+        let to = LevelIndices::<LANES>::new(to);
+        let (top_block, top) = set.enter_top(to.key);
+        let mut cursor = Self {
+            next_key: to.key.checked_sub(1),
+            key: to.key,
+            top_block,
+            top: top & Bits::ones_between(0, to.top),
+            top_index: to.top,
+            middle_block: M::default(),
+            middle: Bits::default(),
+            bottom: Bits::default(),
+            base: LevelIndices::<LANES>::bottom_base(to.key, to.top, to.middle),
+        };
+        unsafe {
+            if cursor.top.unset_unchecked(to.top) {
+                let (middle_block, middle) = set.enter_middle(&cursor.top_block, to.top);
+                cursor.middle_block = middle_block;
+                cursor.middle = middle & Bits::ones_between(0, to.middle);
+                if cursor.middle.unset_unchecked(to.middle) {
+                    cursor.bottom = set.enter_bottom(&cursor.middle_block, to.middle)
+                        & Bits::ones_between(0, to.bottom);
+                }
+            }
+        }
+        cursor
+    }
+
+    fn next_up<S>(&mut self, set: &S) -> Option<usize>
+    where
+        S: BitSetView<LANES, Top = T, Middle = M> + ?Sized,
+    {
+        loop {
+            if let Some(bit) = self.bottom.pop_first_set() {
+                return Some(self.base + bit);
+            }
+            if let Some(bit) = self.middle.pop_first_set() {
+                self.bottom = set.enter_bottom(&self.middle_block, bit);
+                self.base = LevelIndices::<LANES>::bottom_base(self.key, self.top_index, bit);
+                continue;
+            }
+            if let Some(bit) = self.top.pop_first_set() {
+                self.top_index = bit;
+                (self.middle_block, self.middle) = set.enter_middle(&self.top_block, bit);
+                continue;
+            }
+            self.key = set.next_top(self.next_key?)?;
+            self.next_key = self.key.checked_add(1);
+            (self.top_block, self.top) = set.enter_top(self.key);
+        }
+    }
+
+    fn next_down<S>(&mut self, set: &S) -> Option<usize>
+    where
+        S: BitSetView<LANES, Top = T, Middle = M> + ?Sized,
+    {
+        loop {
+            if let Some(bit) = self.bottom.pop_last_set() {
+                return Some(self.base + bit);
+            }
+            if let Some(bit) = self.middle.pop_last_set() {
+                self.bottom = set.enter_bottom(&self.middle_block, bit);
+                self.base = LevelIndices::<LANES>::bottom_base(self.key, self.top_index, bit);
+                continue;
+            }
+            if let Some(bit) = self.top.pop_last_set() {
+                self.top_index = bit;
+                (self.middle_block, self.middle) = set.enter_middle(&self.top_block, bit);
+                continue;
+            }
+            self.key = set.prev_top(self.next_key?)?;
+            self.next_key = self.key.checked_sub(1);
+            (self.top_block, self.top) = set.enter_top(self.key);
+        }
+    }
+}
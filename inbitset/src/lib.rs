@@ -1,48 +1,729 @@
-#![feature(portable_simd)]
+#![cfg_attr(feature = "simd", feature(portable_simd))]
 
+mod atomic;
 mod bits;
+pub mod config;
+mod counts;
+mod cow;
+mod diff;
+pub mod encoding;
+mod iter;
+pub mod map;
+pub mod ops;
+#[cfg(feature = "rayon")]
+mod par;
+mod stats;
+mod view;
 
-use std::mem::MaybeUninit;
+use std::{
+    fmt, mem,
+    ops::{Bound, RangeBounds},
+};
 
-use bits::*;
+use bits::SCALAR_BITS;
+use counts::Counts;
 
-struct BitSet {
-    top: TopBlock,
-    middle: Level<MiddleBlock>,
-    bottom: Level<BottomBlock>,
+pub use atomic::AtomicBitSet;
+pub use bits::Bits;
+pub use config::{BlockIndex, Config};
+pub use cow::{CowBitSet, CowDiff};
+pub use diff::{Change, Diff, diff};
+pub use encoding::{BitSetRef, DecodeError};
+pub use iter::{Drain, Iter};
+pub use map::BitMap;
+pub use ops::{apply, reduce};
+#[cfg(feature = "rayon")]
+pub use par::ParIter;
+pub use stats::{LevelStats, Stats};
+pub use view::BitSetView;
+
+/// Hierarchical bitset over the whole `usize` range.
+///
+/// Bits are grouped into bottom blocks, whose presence is tracked by middle blocks, whose presence
+/// is in turn tracked by top blocks. Each top block covers `Bits::SIZE.pow(3)` indices, and only the
+/// top blocks that are non-empty are kept, listed by their key (the index bits above the ones that
+/// the top block covers). Sets with indices below `Bits::SIZE.pow(3)` therefore have at most one.
+///
+/// Blocks of the same level share one pool, and parents refer to their children by their index in
+/// it. `C` chooses the integer types those indices are stored as, see [`config`]. The list of top
+/// blocks only holds their keys and pool indices, so adding a key to a set spread over many of them
+/// shifts small entries rather than whole blocks.
+///
+/// `LANES` chooses the block geometry, from 64-bit blocks with `1` to 512-bit blocks with `8`, see
+/// [`Bits`]. Larger blocks make the hierarchy shallower and cover more indices per top block, but
+/// waste more memory on sparse sets.
+pub struct BitSet<C: Config = config::Wide, const LANES: usize = 4> {
+    tops: Vec<TopEntry>, // Sorted by key.
+    counts: Counts,      // Number of set bits under each of `tops`.
+    top: Level<TopBlock<C::TopIndex, LANES>>,
+    middle: Level<MiddleBlock<C::MiddleIndex, LANES>>,
+    bottom: Level<BottomBlock<LANES>>,
+    len: usize, // Number of set bits.
 }
 
 impl BitSet {
-    pub fn insert(&mut self, bit_index: usize) {
-        let indices = LevelIndices::new(bit_index);
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<C: Config, const LANES: usize> BitSet<C, LANES> {
+    /// Sets the bit at `bit_index`. Returns true if it was not set before.
+    pub fn insert(&mut self, bit_index: usize) -> bool {
+        let indices = LevelIndices::<LANES>::new(bit_index);
 
         unsafe {
-            self.get_or_insert_bottom_block(indices.top, indices.middle)
-                .bits
-                .set_unchecked(indices.bottom);
+            let (top_position, middle_block_index, bottom_block_index) =
+                self.get_or_insert_path(indices.key, indices.top, indices.middle);
+            let bottom_block = self.bottom.blocks.get_unchecked_mut(bottom_block_index);
+            if bottom_block.bits.set_unchecked(indices.bottom) {
+                return false;
+            }
+            self.middle
+                .blocks
+                .get_unchecked_mut(middle_block_index)
+                .count += 1;
+            self.counts.add(top_position, 1);
+            self.len += 1;
+            true
         }
     }
 
-    pub fn contains(&self, bit_index: usize) {
-        let indices = LevelIndices::new(bit_index);
+    pub fn contains(&self, bit_index: usize) -> bool {
+        let indices = LevelIndices::<LANES>::new(bit_index);
 
         unsafe {
+            let Some(top_block) = self.top(indices.key) else {
+                return false;
+            };
+            let Some(middle_block_index) = top_block.get(indices.top) else {
+                return false;
+            };
+            let Some(bottom_block_index) = self
+                .middle
+                .blocks
+                .get_unchecked(middle_block_index)
+                .get(indices.middle)
+            else {
+                return false;
+            };
             self.bottom
                 .blocks
-                .get_unchecked(indices.middle)
+                .get_unchecked(bottom_block_index)
                 .bits
                 .get_unchecked(indices.bottom)
         }
     }
 
-    unsafe fn get_or_insert_bottom_block(
+    /// Unsets the bit at `bit_index`. Returns true if it was set.
+    ///
+    /// Blocks that become empty are unlinked from their parent and put on the free list of their
+    /// level, to be reused by subsequent inserts.
+    pub fn remove(&mut self, bit_index: usize) -> bool {
+        let indices = LevelIndices::<LANES>::new(bit_index);
+        let mut bits = Bits::default();
+
+        unsafe {
+            bits.set_unchecked(indices.bottom);
+            self.remove_bits(indices.key, indices.top, indices.middle, bits) != 0
+        }
+    }
+
+    /// Sets all bits in `range`.
+    ///
+    /// Whole bottom blocks are filled at once, and each middle and top block on the way is looked
+    /// up or allocated once per bottom block rather than once per bit.
+    pub fn insert_range(&mut self, range: impl RangeBounds<usize>) {
+        let Some((start, end)) = inclusive_bounds(range) else {
+            return;
+        };
+        let mut bit_index = start;
+        loop {
+            let indices = LevelIndices::<LANES>::new(bit_index);
+            let base = bit_index - indices.bottom;
+            let last = end.min(base + (Bits::<LANES>::SIZE - 1));
+            let bits = Bits::ones_between(indices.bottom, last - base);
+            unsafe { self.insert_bits(indices.key, indices.top, indices.middle, bits) };
+            if last == end {
+                break;
+            }
+            bit_index = last + 1;
+        }
+    }
+
+    /// Unsets all bits in `range`.
+    ///
+    /// Only the blocks that exist within the range are visited, and the ones that become empty are
+    /// put on the free lists, like in [`Self::remove`].
+    pub fn remove_range(&mut self, range: impl RangeBounds<usize>) {
+        let Some((start, end)) = inclusive_bounds(range) else {
+            return;
+        };
+        let from = LevelIndices::<LANES>::new(start);
+        let to = LevelIndices::<LANES>::new(end);
+
+        // Bits of a block within the range, given whether the block is on its first or last path.
+        let last = Bits::<LANES>::SIZE - 1;
+        let span = |at_from: bool, from: usize, at_to: bool, to: usize| {
+            Bits::<LANES>::ones_between(
+                if at_from { from } else { 0 },
+                if at_to { to } else { last },
+            )
+        };
+
+        // The bits are copied out before clearing, since clearing may unlink their blocks.
+        let mut position = self.tops.partition_point(|top| top.key < from.key);
+        while let Some(&TopEntry { key, block }) = self.tops.get(position) {
+            if key > to.key {
+                break;
+            }
+            let (at_from, at_to) = (key == from.key, key == to.key);
+            let top_bits = unsafe { self.top.blocks.get_unchecked(block).bits };
+            let top_bits = top_bits & span(at_from, from.top, at_to, to.top);
+            top_bits.for_each_set(|top| {
+                let (at_from, at_to) = (at_from && top == from.top, at_to && top == to.top);
+                let middle_bits =
+                    self.middle_bits(key, top) & span(at_from, from.middle, at_to, to.middle);
+                middle_bits.for_each_set(|middle| {
+                    let at_from = at_from && middle == from.middle;
+                    let at_to = at_to && middle == to.middle;
+                    let bits = span(at_from, from.bottom, at_to, to.bottom);
+                    unsafe { self.remove_bits(key, top, middle, bits) };
+                });
+            });
+            // Unless the top block was emptied and removed, move on to the next one.
+            if self.tops.get(position).is_some_and(|top| top.key == key) {
+                position += 1;
+            }
+        }
+    }
+
+    /// Unsets the bits for which `f` returns false, visiting the set bits in ascending order.
+    ///
+    /// Only populated blocks are visited, and the ones that become empty are put on the free lists,
+    /// like in [`Self::remove`].
+    pub fn retain(&mut self, mut f: impl FnMut(usize) -> bool) {
+        self.retain_blocks(|base, bits| {
+            let mut kept = bits;
+            bits.for_each_set(|bit| {
+                if !f(base + bit) {
+                    unsafe { kept.unset_unchecked(bit) };
+                }
+            });
+            kept
+        });
+    }
+
+    /// Unsets all bits. All blocks are put on the free lists, so the allocated memory is reused by
+    /// subsequent inserts, see [`Self::compact`] to release it.
+    pub fn clear(&mut self) {
+        self.retain_blocks(|_, _| Bits::default());
+    }
+
+    /// Returns an iterator that unsets and yields all set bits, in ascending order. The set is
+    /// cleared when the iterator is dropped, even if it was not fully consumed.
+    pub fn drain(&mut self) -> Drain<'_, C, LANES> {
+        Drain::new(self)
+    }
+
+    /// Creates a set from indices in ascending order, filling a bottom block at a time. Unsorted
+    /// input results in the same set, it only takes longer to build.
+    pub fn from_sorted_iter(iter: impl IntoIterator<Item = usize>) -> Self {
+        let mut set = Self::default();
+        set.extend(iter);
+        set
+    }
+
+    /// Returns the number of set bits.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if no bits are set.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns an iterator over the set bits, in ascending order.
+    pub fn iter(&self) -> Iter<&Self, LANES> {
+        Iter::new(self)
+    }
+
+    /// Returns an iterator over the set bits in `range`, in ascending order.
+    pub fn iter_range(&self, range: impl RangeBounds<usize>) -> Iter<&Self, LANES> {
+        Iter::with_range(self, range)
+    }
+
+    /// Returns a parallel iterator over the set bits, see [`ParIter`].
+    #[cfg(feature = "rayon")]
+    pub fn par_iter(&self) -> ParIter<'_, Self, LANES> {
+        ParIter::new(self)
+    }
+
+    /// Returns the number of set bits below `bit_index`.
+    pub fn rank(&self, bit_index: usize) -> usize {
+        let indices = LevelIndices::<LANES>::new(bit_index);
+
+        unsafe {
+            let (Ok(top_position) | Err(top_position)) = self.top_position(indices.key);
+            let mut rank = self.counts.prefix(top_position);
+
+            let Some(top_block) = self.top(indices.key) else {
+                return rank;
+            };
+            (top_block.bits & Bits::ones_below(indices.top)).for_each_set(|top| {
+                let middle_block = top_block.child_unchecked(top);
+                rank += self.middle.blocks.get_unchecked(middle_block).count as usize;
+            });
+
+            let Some(middle_block_index) = top_block.get(indices.top) else {
+                return rank;
+            };
+            let middle_block = self.middle.blocks.get_unchecked(middle_block_index);
+            (middle_block.bits & Bits::ones_below(indices.middle)).for_each_set(|middle| {
+                let bottom_block = middle_block.child_unchecked(middle);
+                rank += self
+                    .bottom
+                    .blocks
+                    .get_unchecked(bottom_block)
+                    .bits
+                    .count_set();
+            });
+
+            let Some(bottom_block_index) = middle_block.get(indices.middle) else {
+                return rank;
+            };
+            let bottom_block = self.bottom.blocks.get_unchecked(bottom_block_index);
+            rank + (bottom_block.bits & Bits::ones_below(indices.bottom)).count_set()
+        }
+    }
+
+    /// Returns the `n`-th set bit, counting from zero, or `None` if there are not that many.
+    pub fn select(&self, n: usize) -> Option<usize> {
+        if n >= self.len {
+            return None;
+        }
+        let (top_position, mut n) = self.counts.select(n);
+
+        unsafe {
+            let (key, top_block) = self.top_at(top_position);
+            let mut top_bits = top_block.bits;
+            let (top, middle_block) = loop {
+                let top = top_bits.pop_first_set()?;
+                let middle_block = self
+                    .middle
+                    .blocks
+                    .get_unchecked(top_block.child_unchecked(top));
+                let count = middle_block.count as usize;
+                if n < count {
+                    break (top, middle_block);
+                }
+                n -= count;
+            };
+
+            let mut middle_bits = middle_block.bits;
+            loop {
+                let middle = middle_bits.pop_first_set()?;
+                let bottom_block = self
+                    .bottom
+                    .blocks
+                    .get_unchecked(middle_block.child_unchecked(middle));
+                let count = bottom_block.bits.count_set();
+                if n < count {
+                    let bottom = bottom_block.bits.select(n)?;
+                    let base = LevelIndices::<LANES>::bottom_base(key, top, middle);
+                    return Some(base + bottom);
+                }
+                n -= count;
+            }
+        }
+    }
+
+    /// Returns the lowest set bit, or `None` if the set is empty.
+    pub fn first(&self) -> Option<usize> {
+        self.first_from(0)
+    }
+
+    /// Returns the highest set bit, or `None` if the set is empty.
+    pub fn last(&self) -> Option<usize> {
+        self.last_to(usize::MAX)
+    }
+
+    /// Returns the lowest set bit greater than `bit_index`.
+    pub fn next_after(&self, bit_index: usize) -> Option<usize> {
+        self.first_from(bit_index.checked_add(1)?)
+    }
+
+    /// Returns the highest set bit less than `bit_index`.
+    pub fn prev_before(&self, bit_index: usize) -> Option<usize> {
+        self.last_to(bit_index.checked_sub(1)?)
+    }
+
+    /// Unsets the lowest set bit and returns it, or `None` if the set is empty.
+    pub fn pop_first(&mut self) -> Option<usize> {
+        let bit_index = self.first()?;
+        self.remove(bit_index);
+        Some(bit_index)
+    }
+
+    /// Unsets the highest set bit and returns it, or `None` if the set is empty.
+    pub fn pop_last(&mut self) -> Option<usize> {
+        let bit_index = self.last()?;
+        self.remove(bit_index);
+        Some(bit_index)
+    }
+
+    /// Returns true if every bit set in `self` is also set in `other`.
+    ///
+    /// Compares the sets level by level and stops at the first block of `self` whose occupancy
+    /// bits are not covered by the corresponding block of `other`.
+    pub fn is_subset<D: Config>(&self, other: &BitSet<D, LANES>) -> bool {
+        if self.len > other.len {
+            return false;
+        }
+        self.top_blocks().all(|(key, top_block)| unsafe {
+            let Some(other_top_block) = other.top(key) else {
+                return false;
+            };
+            if !(top_block.bits & !other_top_block.bits).is_empty() {
+                return false;
+            }
+            let mut top_bits = top_block.bits;
+            while let Some(top) = top_bits.pop_first_set() {
+                let middle_block = self
+                    .middle
+                    .blocks
+                    .get_unchecked(top_block.child_unchecked(top));
+                let other_middle_block = other
+                    .middle
+                    .blocks
+                    .get_unchecked(other_top_block.child_unchecked(top));
+                if middle_block.count > other_middle_block.count
+                    || !(middle_block.bits & !other_middle_block.bits).is_empty()
+                {
+                    return false;
+                }
+                let mut middle_bits = middle_block.bits;
+                while let Some(middle) = middle_bits.pop_first_set() {
+                    let bottom_block = self
+                        .bottom
+                        .blocks
+                        .get_unchecked(middle_block.child_unchecked(middle));
+                    let other_bottom_block = other
+                        .bottom
+                        .blocks
+                        .get_unchecked(other_middle_block.child_unchecked(middle));
+                    if !(bottom_block.bits & !other_bottom_block.bits).is_empty() {
+                        return false;
+                    }
+                }
+            }
+            true
+        })
+    }
+
+    /// Returns true if every bit set in `other` is also set in `self`.
+    pub fn is_superset<D: Config>(&self, other: &BitSet<D, LANES>) -> bool {
+        other.is_subset(self)
+    }
+
+    /// Returns true if `self` and `other` have no set bits in common.
+    ///
+    /// Only the subtrees present in both sets are compared.
+    pub fn is_disjoint<D: Config>(&self, other: &BitSet<D, LANES>) -> bool {
+        self.top_blocks().all(|(key, top_block)| unsafe {
+            let Some(other_top_block) = other.top(key) else {
+                return true;
+            };
+            let mut top_bits = top_block.bits & other_top_block.bits;
+            while let Some(top) = top_bits.pop_first_set() {
+                let middle_block = self
+                    .middle
+                    .blocks
+                    .get_unchecked(top_block.child_unchecked(top));
+                let other_middle_block = other
+                    .middle
+                    .blocks
+                    .get_unchecked(other_top_block.child_unchecked(top));
+                let mut middle_bits = middle_block.bits & other_middle_block.bits;
+                while let Some(middle) = middle_bits.pop_first_set() {
+                    let bottom_block = self
+                        .bottom
+                        .blocks
+                        .get_unchecked(middle_block.child_unchecked(middle));
+                    let other_bottom_block = other
+                        .bottom
+                        .blocks
+                        .get_unchecked(other_middle_block.child_unchecked(middle));
+                    if !(bottom_block.bits & other_bottom_block.bits).is_empty() {
+                        return false;
+                    }
+                }
+            }
+            true
+        })
+    }
+
+    /// Moves the live blocks to the front of their pools and releases the rest.
+    ///
+    /// Blocks freed by removals stay allocated for reuse by later inserts, so a set keeps the
+    /// memory of its largest size. Compacting renumbers the live blocks densely, rewrites the
+    /// indices of their parents and shrinks the pools to fit, see [`Self::memory_usage`].
+    pub fn compact(&mut self) {
+        self.tops.shrink_to_fit();
+        self.counts.shrink_to_fit();
+        self.top
+            .compact(self.tops.iter_mut().map(|top| &mut top.block));
+        // All top blocks are live now.
+        self.middle
+            .compact(self.top.blocks.iter_mut().flat_map(TopBlock::children_mut));
+        // All middle blocks are live now.
+        self.bottom.compact(
+            self.middle
+                .blocks
+                .iter_mut()
+                .flat_map(MiddleBlock::children_mut),
+        );
+    }
+
+    /// Returns the number of bytes used by the set, including the blocks on the free lists and the
+    /// unused capacity of the pools.
+    pub fn memory_usage(&self) -> usize {
+        mem::size_of::<Self>()
+            + self.tops.capacity() * mem::size_of::<TopEntry>()
+            + self.counts.memory_usage()
+            + self.top.memory_usage()
+            + self.middle.memory_usage()
+            + self.bottom.memory_usage()
+    }
+
+    /// Returns the lowest set bit not less than `bit_index`.
+    fn first_from(&self, bit_index: usize) -> Option<usize> {
+        let indices = LevelIndices::<LANES>::new(bit_index);
+        let position = self.tops.partition_point(|top| top.key < indices.key);
+        let top = *self.tops.get(position)?;
+        if top.key != indices.key {
+            return self.first_in_top(top, 0, 0, 0);
+        }
+        self.first_in_top(top, indices.top, indices.middle, indices.bottom)
+            .or_else(|| self.first_in_top(*self.tops.get(position + 1)?, 0, 0, 0))
+    }
+
+    /// Returns the lowest set bit of the top block of `top` that is not before the given path.
+    fn first_in_top(
+        &self,
+        top: TopEntry,
+        from_top: usize,
+        from_middle: usize,
+        from_bottom: usize,
+    ) -> Option<usize> {
+        unsafe {
+            let TopEntry { key, block } = top;
+            let top_block = self.top.blocks.get_unchecked(block);
+            let mut top_bits = top_block.bits & !Bits::ones_below(from_top);
+            while let Some(top) = top_bits.pop_first_set() {
+                let middle_block = self
+                    .middle
+                    .blocks
+                    .get_unchecked(top_block.child_unchecked(top));
+                let from_middle = if top == from_top { from_middle } else { 0 };
+                let mut middle_bits = middle_block.bits & !Bits::ones_below(from_middle);
+                while let Some(middle) = middle_bits.pop_first_set() {
+                    let bottom_block = self
+                        .bottom
+                        .blocks
+                        .get_unchecked(middle_block.child_unchecked(middle));
+                    let from_bottom = if top == from_top && middle == from_middle {
+                        from_bottom
+                    } else {
+                        0
+                    };
+                    let bottom_bits = bottom_block.bits & !Bits::ones_below(from_bottom);
+                    if let Some(bottom) = bottom_bits.first_set() {
+                        let base = LevelIndices::<LANES>::bottom_base(key, top, middle);
+                        return Some(base + bottom);
+                    }
+                }
+            }
+            None
+        }
+    }
+
+    /// Returns the highest set bit not greater than `bit_index`.
+    fn last_to(&self, bit_index: usize) -> Option<usize> {
+        let last = Bits::<LANES>::SIZE - 1;
+
+        let indices = LevelIndices::<LANES>::new(bit_index);
+        let position = self.tops.partition_point(|top| top.key <= indices.key);
+        let top = *self.tops.get(position.checked_sub(1)?)?;
+        if top.key != indices.key {
+            return self.last_in_top(top, last, last, last);
+        }
+        self.last_in_top(top, indices.top, indices.middle, indices.bottom)
+            .or_else(|| {
+                self.last_in_top(*self.tops.get(position.checked_sub(2)?)?, last, last, last)
+            })
+    }
+
+    /// Returns the highest set bit of the top block of `top` that is not after the given path.
+    fn last_in_top(
+        &self,
+        top: TopEntry,
+        to_top: usize,
+        to_middle: usize,
+        to_bottom: usize,
+    ) -> Option<usize> {
+        unsafe {
+            let TopEntry { key, block } = top;
+            let top_block = self.top.blocks.get_unchecked(block);
+            let mut top_bits = top_block.bits & Bits::ones_below(to_top + 1);
+            while let Some(top) = top_bits.pop_last_set() {
+                let middle_block = self
+                    .middle
+                    .blocks
+                    .get_unchecked(top_block.child_unchecked(top));
+                let to_middle = if top == to_top {
+                    to_middle
+                } else {
+                    Bits::<LANES>::SIZE - 1
+                };
+                let mut middle_bits = middle_block.bits & Bits::ones_below(to_middle + 1);
+                while let Some(middle) = middle_bits.pop_last_set() {
+                    let bottom_block = self
+                        .bottom
+                        .blocks
+                        .get_unchecked(middle_block.child_unchecked(middle));
+                    let to_bottom = if top == to_top && middle == to_middle {
+                        to_bottom
+                    } else {
+                        Bits::<LANES>::SIZE - 1
+                    };
+                    let bottom_bits = bottom_block.bits & Bits::ones_below(to_bottom + 1);
+                    if let Some(bottom) = bottom_bits.last_set() {
+                        let base = LevelIndices::<LANES>::bottom_base(key, top, middle);
+                        return Some(base + bottom);
+                    }
+                }
+            }
+            None
+        }
+    }
+
+    /// Replaces the bits of every bottom block with the ones `f` returns for it, passing it the
+    /// first index of the block and its bits. Only populated blocks are visited, and the ones that
+    /// become empty are unlinked and put on the free lists. `f` can not set bits that were unset.
+    fn retain_blocks(&mut self, mut f: impl FnMut(usize, Bits<LANES>) -> Bits<LANES>) {
+        let (top_level, middle, bottom) = (&mut self.top, &mut self.middle, &mut self.bottom);
+        let mut counts = Vec::new();
+
+        self.tops.retain(|&TopEntry { key, block }| unsafe {
+            let top_block = top_level.blocks.get_unchecked_mut(block);
+            let mut top_count = 0;
+            let top_bits = top_block.bits;
+            top_bits.for_each_set(|top| {
+                let middle_block_index = top_block.child_unchecked(top);
+                let middle_block = middle.blocks.get_unchecked_mut(middle_block_index);
+                let mut middle_count = 0;
+                let middle_bits = middle_block.bits;
+                middle_bits.for_each_set(|middle| {
+                    let bottom_block_index = middle_block.child_unchecked(middle);
+                    let bottom_block = bottom.blocks.get_unchecked_mut(bottom_block_index);
+                    let base = LevelIndices::<LANES>::bottom_base(key, top, middle);
+                    bottom_block.bits = bottom_block.bits & f(base, bottom_block.bits);
+                    if bottom_block.bits.is_empty() {
+                        bottom.remove_block(bottom_block_index);
+                        middle_block.bits.unset_unchecked(middle);
+                    } else {
+                        middle_count += bottom_block.bits.count_set();
+                    }
+                });
+
+                middle_block.count = middle_count as u32;
+                if middle_block.bits.is_empty() {
+                    middle.remove_block(middle_block_index);
+                    top_block.bits.unset_unchecked(top);
+                }
+                top_count += middle_count;
+            });
+
+            if top_block.bits.is_empty() {
+                top_level.remove_block(block);
+                return false;
+            }
+            counts.push(top_count);
+            true
+        });
+        self.len = counts.iter().sum();
+        self.counts.rebuild(counts);
+    }
+
+    /// Returns the position of the top block with `key` in `tops`, or the position where it
+    /// should be inserted.
+    fn top_position(&self, key: usize) -> Result<usize, usize> {
+        self.tops.binary_search_by_key(&key, |top| top.key)
+    }
+
+    fn top(&self, key: usize) -> Option<&TopBlock<C::TopIndex, LANES>> {
+        let position = self.top_position(key).ok()?;
+        Some(unsafe { self.top_at(position).1 })
+    }
+
+    /// Returns the key of the top block at `position` in `tops`, and the block.
+    ///
+    /// # Safety
+    /// The caller must ensure that `position` is less than `tops.len()`.
+    unsafe fn top_at(&self, position: usize) -> (usize, &TopBlock<C::TopIndex, LANES>) {
+        unsafe {
+            let TopEntry { key, block } = *self.tops.get_unchecked(position);
+            (key, self.top.blocks.get_unchecked(block))
+        }
+    }
+
+    /// Returns the non-empty top blocks with their keys, in order.
+    fn top_blocks(&self) -> impl Iterator<Item = (usize, &TopBlock<C::TopIndex, LANES>)> + Clone {
+        (0..self.tops.len()).map(|position| unsafe { self.top_at(position) })
+    }
+
+    /// Returns the position of the top block in `tops` and the indices of the middle and bottom
+    /// blocks at the given path, creating the blocks that do not exist yet.
+    ///
+    /// # Safety
+    /// The caller must ensure that `top_index` and `middle_index` are less than `Bits::SIZE`.
+    unsafe fn get_or_insert_path(
         &mut self,
+        key: usize,
         top_index: usize,
         middle_index: usize,
-    ) -> &mut BottomBlock {
+    ) -> (usize, usize, usize) {
         unsafe {
+            // Check that the blocks to be created get indices that fit the config before changing
+            // anything, so that running out of indices panics with the set left as it was.
+            let middle_block_index = self.top(key).and_then(|top_block| top_block.get(top_index));
+            let bottom_block_index = middle_block_index.and_then(|middle_block_index| {
+                let middle_block = self.middle.blocks.get_unchecked(middle_block_index);
+                middle_block.get(middle_index)
+            });
+            if middle_block_index.is_none() {
+                self.middle.check_next_index::<C::TopIndex>();
+            }
+            if bottom_block_index.is_none() {
+                self.bottom.check_next_index::<C::MiddleIndex>();
+            }
+
+            let top_position = match self.top_position(key) {
+                Ok(position) => position,
+                Err(position) => {
+                    let block = self.top.insert_block();
+                    self.tops.insert(position, TopEntry { key, block });
+                    self.counts.insert(position);
+                    position
+                }
+            };
+
             let middle_block_index = self
                 .top
+                .blocks
+                .get_unchecked_mut(self.tops.get_unchecked(top_position).block)
                 .get_or_insert(top_index, || self.middle.insert_block());
 
             let bottom_block_index = self
@@ -51,17 +732,269 @@ impl BitSet {
                 .get_unchecked_mut(middle_block_index)
                 .get_or_insert(middle_index, || self.bottom.insert_block());
 
-            self.bottom.blocks.get_unchecked_mut(bottom_block_index)
+            (top_position, middle_block_index, bottom_block_index)
+        }
+    }
+
+    /// Unsets all of `bits` in the bottom block at the given path, unlinking the blocks that
+    /// become empty. Returns the number of bits that were set before.
+    ///
+    /// # Safety
+    /// The caller must ensure that `top_index` and `middle_index` are less than `Bits::SIZE`.
+    unsafe fn remove_bits(
+        &mut self,
+        key: usize,
+        top_index: usize,
+        middle_index: usize,
+        bits: Bits<LANES>,
+    ) -> usize {
+        unsafe {
+            let Ok(top_position) = self.top_position(key) else {
+                return 0;
+            };
+            let top_block_index = self.tops.get_unchecked(top_position).block;
+            let top_block = self.top.blocks.get_unchecked_mut(top_block_index);
+            let Some(middle_block_index) = top_block.get(top_index) else {
+                return 0;
+            };
+            let middle_block = self.middle.blocks.get_unchecked_mut(middle_block_index);
+            let Some(bottom_block_index) = middle_block.get(middle_index) else {
+                return 0;
+            };
+            let bottom_block = self.bottom.blocks.get_unchecked_mut(bottom_block_index);
+            let removed = (bits & bottom_block.bits).count_set();
+            if removed == 0 {
+                return 0;
+            }
+            bottom_block.bits = bottom_block.bits & !bits;
+            middle_block.count -= removed as u32;
+            self.counts.sub(top_position, removed);
+            self.len -= removed;
+            if !bottom_block.bits.is_empty() {
+                return removed;
+            }
+
+            // The bottom block is empty now, unlink it from the middle level.
+            self.bottom.remove_block(bottom_block_index);
+            middle_block.bits.unset_unchecked(middle_index);
+            if !middle_block.bits.is_empty() {
+                return removed;
+            }
+
+            // Same for the middle block.
+            self.middle.remove_block(middle_block_index);
+            top_block.bits.unset_unchecked(top_index);
+            if top_block.bits.is_empty() {
+                self.top.remove_block(top_block_index);
+                self.tops.remove(top_position);
+                self.counts.remove(top_position);
+            }
+            removed
+        }
+    }
+
+    /// Sets all of `bits` in the bottom block at the given path, creating the blocks that do not
+    /// exist yet. Returns the number of bits that were not set before.
+    ///
+    /// # Safety
+    /// The caller must ensure that `top_index` and `middle_index` are less than `Bits::SIZE`, and
+    /// that `bits` is not empty.
+    unsafe fn insert_bits(
+        &mut self,
+        key: usize,
+        top_index: usize,
+        middle_index: usize,
+        bits: Bits<LANES>,
+    ) -> usize {
+        unsafe {
+            let (top_position, middle_block_index, bottom_block_index) =
+                self.get_or_insert_path(key, top_index, middle_index);
+            let bottom_block = self.bottom.blocks.get_unchecked_mut(bottom_block_index);
+            let inserted = (bits & !bottom_block.bits).count_set();
+            bottom_block.bits = bottom_block.bits | bits;
+            self.middle
+                .blocks
+                .get_unchecked_mut(middle_block_index)
+                .count += inserted as u32;
+            self.counts.add(top_position, inserted);
+            self.len += inserted;
+            inserted
         }
     }
 }
 
-#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Debug)]
+impl<C: Config, const LANES: usize> Clone for BitSet<C, LANES> {
+    fn clone(&self) -> Self {
+        Self {
+            tops: self.tops.clone(),
+            counts: self.counts.clone(),
+            top: self.top.clone(),
+            middle: self.middle.clone(),
+            bottom: self.bottom.clone(),
+            len: self.len,
+        }
+    }
+}
+
+/// Compares the set bits, regardless of how the blocks are laid out in memory or which config the
+/// sets use.
+impl<C: Config, D: Config, const LANES: usize> PartialEq<BitSet<D, LANES>> for BitSet<C, LANES> {
+    fn eq(&self, other: &BitSet<D, LANES>) -> bool {
+        self.len == other.len && self.tops.len() == other.tops.len() && self.is_subset(other)
+    }
+}
+
+impl<C: Config, const LANES: usize> Eq for BitSet<C, LANES> {}
+
+impl<C: Config, const LANES: usize> Default for BitSet<C, LANES> {
+    fn default() -> Self {
+        Self {
+            tops: Vec::new(),
+            counts: Counts::default(),
+            top: Level::default(),
+            middle: Level::default(),
+            bottom: Level::default(),
+            len: 0,
+        }
+    }
+}
+
+/// Formats the set bits as ranges, like `{0..=9, 15}`. The alternate form, `{:#?}`, dumps the
+/// tree of blocks instead, with the set bits of every bottom block under the path leading to it.
+impl<C: Config, const LANES: usize> fmt::Debug for BitSet<C, LANES> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !f.alternate() {
+            return fmt_ranges(f, self.iter());
+        }
+        let tops = fmt_with(|f| {
+            let tops = self
+                .top_blocks()
+                .enumerate()
+                .map(|(position, (key, top_block))| {
+                    let count = self.counts.get(position);
+                    fmt_with(move |f| self.fmt_top_block(f, key, count, top_block))
+                });
+            f.debug_list().entries(tops).finish()
+        });
+        f.debug_struct("BitSet")
+            .field("len", &self.len)
+            .field("tops", &tops)
+            .finish()
+    }
+}
+
+impl<C: Config, const LANES: usize> BitSet<C, LANES> {
+    fn fmt_top_block(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        key: usize,
+        count: usize,
+        top_block: &TopBlock<C::TopIndex, LANES>,
+    ) -> fmt::Result {
+        let children = fmt_with(|f| {
+            let mut children = f.debug_map();
+            top_block.bits.for_each_set(|top| {
+                let middle_block = unsafe {
+                    self.middle
+                        .blocks
+                        .get_unchecked(top_block.child_unchecked(top))
+                };
+                children.entry(&top, &fmt_with(|f| self.fmt_middle_block(f, middle_block)));
+            });
+            children.finish()
+        });
+        f.debug_struct("TopBlock")
+            .field("key", &key)
+            .field("count", &count)
+            .field("children", &children)
+            .finish()
+    }
+
+    fn fmt_middle_block(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        middle_block: &MiddleBlock<C::MiddleIndex, LANES>,
+    ) -> fmt::Result {
+        let children = fmt_with(|f| {
+            let mut children = f.debug_map();
+            middle_block.bits.for_each_set(|middle| {
+                let bottom_block = unsafe {
+                    self.bottom
+                        .blocks
+                        .get_unchecked(middle_block.child_unchecked(middle))
+                };
+                children.entry(&middle, &bottom_block.bits);
+            });
+            children.finish()
+        });
+        f.debug_struct("MiddleBlock")
+            .field("count", &middle_block.count)
+            .field("children", &children)
+            .finish()
+    }
+}
+
+impl<C: Config, const LANES: usize> Extend<usize> for BitSet<C, LANES> {
+    /// Collects consecutive indices of the same bottom block and sets them all at once, so sorted
+    /// input is inserted a block at a time.
+    fn extend<T: IntoIterator<Item = usize>>(&mut self, iter: T) {
+        let mut block: Option<(LevelIndices<LANES>, Bits<LANES>)> = None;
+        for bit_index in iter {
+            let indices = LevelIndices::<LANES>::new(bit_index);
+            let (path, mut bits) = match block {
+                Some((path, bits)) if path.same_bottom_block(&indices) => (path, bits),
+                _ => {
+                    if let Some((path, bits)) = block {
+                        unsafe { self.insert_bits(path.key, path.top, path.middle, bits) };
+                    }
+                    (indices, Bits::default())
+                }
+            };
+            unsafe { bits.set_unchecked(indices.bottom) };
+            block = Some((path, bits));
+        }
+        if let Some((path, bits)) = block {
+            unsafe { self.insert_bits(path.key, path.top, path.middle, bits) };
+        }
+    }
+}
+
+impl<'a, C: Config, const LANES: usize> Extend<&'a usize> for BitSet<C, LANES> {
+    fn extend<T: IntoIterator<Item = &'a usize>>(&mut self, iter: T) {
+        self.extend(iter.into_iter().copied());
+    }
+}
+
+impl<C: Config, const LANES: usize> FromIterator<usize> for BitSet<C, LANES> {
+    fn from_iter<T: IntoIterator<Item = usize>>(iter: T) -> Self {
+        Self::from_sorted_iter(iter)
+    }
+}
+
+impl<'a, C: Config, const LANES: usize> IntoIterator for &'a BitSet<C, LANES> {
+    type Item = usize;
+    type IntoIter = Iter<&'a BitSet<C, LANES>, LANES>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 struct Level<B> {
     blocks: Vec<B>,
     empty: usize, // usize::MAX when N/A.
 }
 
+impl<B> Default for Level<B> {
+    fn default() -> Self {
+        Self {
+            blocks: Vec::new(),
+            empty: usize::MAX,
+        }
+    }
+}
+
 impl<B> Level<B>
 where
     B: Block,
@@ -72,15 +1005,23 @@ where
         }
         let index = self.empty;
         unsafe {
-            self.empty = self
-                .blocks
-                .get_unchecked_mut(index)
-                .bits_mut()
-                .take_empty_block();
+            self.empty = self.blocks.get_unchecked_mut(index).take_empty_block();
         }
         Some(index)
     }
 
+    /// Panics if the index of the next inserted block does not fit `I`.
+    fn check_next_index<I: BlockIndex>(&self) {
+        let index = match self.empty {
+            usize::MAX => self.blocks.len(),
+            empty => empty,
+        };
+        assert!(
+            I::from_usize(index).is_some(),
+            "too many blocks for the index width of the config"
+        );
+    }
+
     fn insert_block(&mut self) -> usize {
         if let Some(index) = self.pop_empty_block() {
             index
@@ -90,125 +1031,318 @@ where
             index
         }
     }
+
+    /// Moves the blocks referenced by `children` to the front of the pool, updating the references,
+    /// and drops all other blocks. Every live block must be referenced exactly once.
+    fn compact<'a, I: BlockIndex>(&mut self, children: impl Iterator<Item = &'a mut I>) {
+        let children: Vec<_> = children.collect();
+        let len = children.len();
+        let mut live = vec![false; self.blocks.len()];
+        for child in &children {
+            live[child.to_usize()] = true;
+        }
+        let mut holes = (0..len).filter(|&index| !live[index]);
+        for child in children {
+            let index = child.to_usize();
+            if index >= len {
+                let hole = holes.next().expect("more live blocks than references");
+                self.blocks.swap(hole, index);
+                *child = I::from_usize(hole).expect("failed to compress block index");
+            }
+        }
+        self.blocks.truncate(len);
+        self.blocks.shrink_to_fit();
+        self.empty = usize::MAX;
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.blocks.capacity() * mem::size_of::<B>()
+    }
+
+    /// Pushes the block at `index` onto the free list. The block has to be empty and unlinked from
+    /// its parent.
+    ///
+    /// # Safety
+    /// The caller must ensure that `index` is in bounds.
+    unsafe fn remove_block(&mut self, index: usize) {
+        unsafe {
+            self.blocks
+                .get_unchecked_mut(index)
+                .set_empty_block(self.empty);
+        }
+        self.empty = index;
+    }
 }
 
+/// A pooled block, threaded onto its level's free list through its bits while empty.
 trait Block: Default {
-    fn bits_mut(&mut self) -> &mut Bits;
+    fn take_empty_block(&mut self) -> usize;
+    fn set_empty_block(&mut self, next: usize);
 }
 
+macro_rules! impl_block {
+    ($($block:ty => [$($generics:tt)*]),*) => {$(
+        impl<$($generics)*> Block for $block {
+            fn take_empty_block(&mut self) -> usize {
+                self.bits.take_empty_block()
+            }
+
+            fn set_empty_block(&mut self, next: usize) {
+                debug_assert!(self.bits.is_empty());
+                self.bits.set_empty_block(next);
+            }
+        }
+    )*};
+}
+
+impl_block!(
+    TopBlock<I, LANES> => [I: BlockIndex, const LANES: usize],
+    MiddleBlock<I, LANES> => [I: BlockIndex, const LANES: usize],
+    BottomBlock<LANES> => [const LANES: usize]
+);
+
+/// Where the top block with `key` is in its pool.
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
-struct TopBlock {
-    bits: Bits,
-    indices: [u8; 256],
+struct TopEntry {
+    key: usize,
+    block: usize,
 }
 
-impl TopBlock {
-    unsafe fn get_or_insert(&mut self, bit_index: usize, f: impl FnOnce() -> usize) -> usize {
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct TopBlock<I, const LANES: usize> {
+    bits: Bits<LANES>,
+    indices: [[I; SCALAR_BITS]; LANES], // One row per lane, see `Bits`.
+}
+
+impl<I: BlockIndex, const LANES: usize> Default for TopBlock<I, LANES> {
+    fn default() -> Self {
+        Self {
+            bits: Bits::default(),
+            indices: [[I::default(); SCALAR_BITS]; LANES],
+        }
+    }
+}
+
+impl<I: BlockIndex, const LANES: usize> TopBlock<I, LANES> {
+    /// Returns the indices of the child blocks.
+    fn children_mut(&mut self) -> impl Iterator<Item = &mut I> {
+        let bits = self.bits;
+        self.indices
+            .as_flattened_mut()
+            .iter_mut()
+            .enumerate()
+            .filter_map(move |(bit_index, index)| {
+                unsafe { bits.get_unchecked(bit_index) }.then_some(index)
+            })
+    }
+
+    /// Returns the index of the child block at `bit_index`, if there is any.
+    ///
+    /// # Safety
+    /// The caller must ensure that `bit_index` is less than `Bits::SIZE`.
+    unsafe fn get(&self, bit_index: usize) -> Option<usize> {
         unsafe {
-            let exists = self.bits.set_unchecked(bit_index);
-            if exists {
-                let compressed = *self.indices.get_unchecked(bit_index);
-                let block_index = compressed
-                    .try_into()
-                    .expect("failed to decompress block index");
-                block_index
+            if self.bits.get_unchecked(bit_index) {
+                Some(self.child_unchecked(bit_index))
             } else {
-                let block_index = f();
-                let compressed = block_index
-                    .try_into()
-                    .expect("failed to compress block index");
-                *self.indices.get_unchecked_mut(bit_index) = compressed;
-                block_index
+                None
             }
         }
     }
-}
 
-impl Block for TopBlock {
-    fn bits_mut(&mut self) -> &mut Bits {
-        &mut self.bits
+    /// Returns the index of the child block at `bit_index` without checking that it exists.
+    ///
+    /// # Safety
+    /// The caller must ensure that `bit_index` is less than `Bits::SIZE` and that the bit is set.
+    unsafe fn child_unchecked(&self, bit_index: usize) -> usize {
+        unsafe {
+            self.indices
+                .as_flattened()
+                .get_unchecked(bit_index)
+                .to_usize()
+        }
     }
+
+    unsafe fn get_or_insert(&mut self, bit_index: usize, f: impl FnOnce() -> usize) -> usize {
+        unsafe {
+            if let Some(block_index) = self.get(bit_index) {
+                return block_index;
+            }
+            let block_index = f();
+            let compressed = I::from_usize(block_index).expect("failed to compress block index");
+            // Only link the child once its index is stored.
+            *self.indices.as_flattened_mut().get_unchecked_mut(bit_index) = compressed;
+            self.bits.set_unchecked(bit_index);
+            block_index
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct MiddleBlock<I, const LANES: usize> {
+    count: u32, // Number of set bits in the subtree.
+    bits: Bits<LANES>,
+    indices: [[I; SCALAR_BITS]; LANES],
 }
 
-impl Default for TopBlock {
+impl<I: BlockIndex, const LANES: usize> Default for MiddleBlock<I, LANES> {
     fn default() -> Self {
-        unsafe { MaybeUninit::zeroed().assume_init() }
+        Self {
+            count: 0,
+            bits: Bits::default(),
+            indices: [[I::default(); SCALAR_BITS]; LANES],
+        }
     }
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
-struct MiddleBlock {
-    bits: Bits,
-    indices: [u16; 256],
-}
+impl<I: BlockIndex, const LANES: usize> MiddleBlock<I, LANES> {
+    /// Returns the indices of the child blocks.
+    fn children_mut(&mut self) -> impl Iterator<Item = &mut I> {
+        let bits = self.bits;
+        self.indices
+            .as_flattened_mut()
+            .iter_mut()
+            .enumerate()
+            .filter_map(move |(bit_index, index)| {
+                unsafe { bits.get_unchecked(bit_index) }.then_some(index)
+            })
+    }
 
-impl MiddleBlock {
-    unsafe fn get_or_insert(&mut self, bit_index: usize, f: impl FnOnce() -> usize) -> usize {
+    /// Returns the index of the child block at `bit_index`, if there is any.
+    ///
+    /// # Safety
+    /// The caller must ensure that `bit_index` is less than `Bits::SIZE`.
+    unsafe fn get(&self, bit_index: usize) -> Option<usize> {
         unsafe {
-            let exists = self.bits.set_unchecked(bit_index);
-            if exists {
-                let compressed = *self.indices.get_unchecked(bit_index);
-                let block_index = compressed
-                    .try_into()
-                    .expect("failed to decompress block index");
-                block_index
+            if self.bits.get_unchecked(bit_index) {
+                Some(self.child_unchecked(bit_index))
             } else {
-                let block_index = f();
-                let compressed = block_index
-                    .try_into()
-                    .expect("failed to compress block index");
-                *self.indices.get_unchecked_mut(bit_index) = compressed;
-                block_index
+                None
             }
         }
     }
-}
 
-impl Block for MiddleBlock {
-    fn bits_mut(&mut self) -> &mut Bits {
-        &mut self.bits
+    /// Returns the index of the child block at `bit_index` without checking that it exists.
+    ///
+    /// # Safety
+    /// The caller must ensure that `bit_index` is less than `Bits::SIZE` and that the bit is set.
+    unsafe fn child_unchecked(&self, bit_index: usize) -> usize {
+        unsafe {
+            self.indices
+                .as_flattened()
+                .get_unchecked(bit_index)
+                .to_usize()
+        }
     }
-}
 
-impl Default for MiddleBlock {
-    fn default() -> Self {
-        unsafe { MaybeUninit::zeroed().assume_init() }
+    unsafe fn get_or_insert(&mut self, bit_index: usize, f: impl FnOnce() -> usize) -> usize {
+        unsafe {
+            if let Some(block_index) = self.get(bit_index) {
+                return block_index;
+            }
+            let block_index = f();
+            let compressed = I::from_usize(block_index).expect("failed to compress block index");
+            // Only link the child once its index is stored.
+            *self.indices.as_flattened_mut().get_unchecked_mut(bit_index) = compressed;
+            self.bits.set_unchecked(bit_index);
+            block_index
+        }
     }
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Debug)]
-struct BottomBlock {
-    bits: Bits,
-}
-
-impl Block for BottomBlock {
-    fn bits_mut(&mut self) -> &mut Bits {
-        &mut self.bits
-    }
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+struct BottomBlock<const LANES: usize> {
+    bits: Bits<LANES>,
 }
 
 #[derive(Copy, Clone, Debug)]
-struct LevelIndices {
+struct LevelIndices<const LANES: usize> {
+    key: usize,
     top: usize,
     middle: usize,
     bottom: usize,
 }
 
-impl LevelIndices {
-    const fn new(bit_index: usize) -> Self {
-        const BOTTOM_BLOCK_CAP: usize = 1 << 8;
-        const MIDDLE_BLOCK_CAP: usize = 1 << 16;
+impl<const LANES: usize> LevelIndices<LANES> {
+    const BOTTOM_BLOCK_CAP: usize = Bits::<LANES>::SIZE;
+    const MIDDLE_BLOCK_CAP: usize = Bits::<LANES>::SIZE * Bits::<LANES>::SIZE;
+    const TOP_BLOCK_CAP: usize = Bits::<LANES>::SIZE * Bits::<LANES>::SIZE * Bits::<LANES>::SIZE;
 
-        let top = bit_index / MIDDLE_BLOCK_CAP;
-        let top_rem = bit_index % MIDDLE_BLOCK_CAP;
-        let middle = top_rem / BOTTOM_BLOCK_CAP;
-        let middle_rem = top_rem % BOTTOM_BLOCK_CAP;
+    const fn new(bit_index: usize) -> Self {
+        let key = bit_index / Self::TOP_BLOCK_CAP;
+        let key_rem = bit_index % Self::TOP_BLOCK_CAP;
+        let top = key_rem / Self::MIDDLE_BLOCK_CAP;
+        let top_rem = key_rem % Self::MIDDLE_BLOCK_CAP;
+        let middle = top_rem / Self::BOTTOM_BLOCK_CAP;
+        let middle_rem = top_rem % Self::BOTTOM_BLOCK_CAP;
         let bottom = middle_rem;
 
         Self {
+            key,
             top,
             middle,
             bottom,
         }
     }
+
+    /// Returns true if both indices are in the same bottom block.
+    const fn same_bottom_block(&self, other: &Self) -> bool {
+        self.key == other.key && self.top == other.top && self.middle == other.middle
+    }
+
+    /// Returns the first bit index of the bottom block at the given path.
+    const fn bottom_base(key: usize, top: usize, middle: usize) -> usize {
+        key * Self::TOP_BLOCK_CAP + top * Self::MIDDLE_BLOCK_CAP + middle * Self::BOTTOM_BLOCK_CAP
+    }
+}
+
+/// Returns the first and last index of `range`, or `None` if it is empty.
+pub(crate) fn inclusive_bounds(range: impl RangeBounds<usize>) -> Option<(usize, usize)> {
+    let start = match range.start_bound() {
+        Bound::Included(&start) => start,
+        Bound::Excluded(&start) => start.checked_add(1)?,
+        Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+        Bound::Included(&end) => end,
+        Bound::Excluded(&end) => end.checked_sub(1)?,
+        Bound::Unbounded => usize::MAX,
+    };
+    (start <= end).then_some((start, end))
+}
+
+/// Formats `indices`, which must be ascending, as a set of ranges of consecutive indices.
+pub(crate) fn fmt_ranges(
+    f: &mut fmt::Formatter<'_>,
+    indices: impl IntoIterator<Item = usize>,
+) -> fmt::Result {
+    let mut set = f.debug_set();
+    let mut indices = indices.into_iter().peekable();
+    while let Some(start) = indices.next() {
+        let mut end = start;
+        while let Some(next) = indices.next_if(|&next| Some(next) == end.checked_add(1)) {
+            end = next;
+        }
+        set.entry(&fmt_with(|f| {
+            if start == end {
+                write!(f, "{start}")
+            } else {
+                write!(f, "{start}..={end}")
+            }
+        }));
+    }
+    set.finish()
+}
+
+/// Implements `Debug` with a closure.
+struct FmtWith<F>(F);
+
+fn fmt_with<F: Fn(&mut fmt::Formatter<'_>) -> fmt::Result>(f: F) -> FmtWith<F> {
+    FmtWith(f)
+}
+
+impl<F: Fn(&mut fmt::Formatter<'_>) -> fmt::Result> fmt::Debug for FmtWith<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (self.0)(f)
+    }
 }
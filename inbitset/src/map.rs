@@ -0,0 +1,340 @@
+//! Sparse map from indices to values, built on the hierarchy of [`BitSet`].
+
+use std::{fmt, iter::FusedIterator, marker::PhantomData, mem, slice};
+
+use crate::{BitSet, Bits, Config, LevelIndices, config};
+
+/// Map from `usize` indices to values of `T`, stored along the hierarchy of a [`BitSet`] of its
+/// keys.
+///
+/// The keys are routed through the same top and middle blocks as in a `BitSet`, and every bottom
+/// block of the keys owns a packed array with the values of its set bits, ordered like the bits.
+/// The value of a key is found by the rank of its bit within the bottom block, so the map takes
+/// about as much memory as its keys and values, without space reserved for missing indices.
+///
+/// Inserting or removing a value moves the other values of the same bottom block, which is at most
+/// `Bits::SIZE` of them.
+pub struct BitMap<T, C: Config = config::Wide, const LANES: usize = 4> {
+    keys: BitSet<C, LANES>,
+    /// The values of every bottom block of `keys`, by the index of the block in its pool. Blocks on
+    /// the free list have no values.
+    values: Vec<Vec<T>>,
+}
+
+impl<T> BitMap<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<T, C: Config, const LANES: usize> BitMap<T, C, LANES> {
+    /// Inserts `value` at `index`. Returns the previous value, if there was any.
+    pub fn insert(&mut self, index: usize, value: T) -> Option<T> {
+        let inserted = self.keys.insert(index);
+        let (bottom_block_index, rank) = self.position(index).expect("the key was just inserted");
+        if self.values.len() <= bottom_block_index {
+            self.values.resize_with(bottom_block_index + 1, Vec::new);
+        }
+
+        let values = &mut self.values[bottom_block_index];
+        if inserted {
+            values.insert(rank, value);
+            None
+        } else {
+            Some(mem::replace(&mut values[rank], value))
+        }
+    }
+
+    /// Removes the value at `index` and returns it, if there was any.
+    pub fn remove(&mut self, index: usize) -> Option<T> {
+        let (bottom_block_index, rank) = self.position(index)?;
+        self.keys.remove(index);
+        Some(self.values[bottom_block_index].remove(rank))
+    }
+
+    pub fn get(&self, index: usize) -> Option<&T> {
+        let (bottom_block_index, rank) = self.position(index)?;
+        Some(&self.values[bottom_block_index][rank])
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        let (bottom_block_index, rank) = self.position(index)?;
+        Some(&mut self.values[bottom_block_index][rank])
+    }
+
+    pub fn contains_key(&self, index: usize) -> bool {
+        self.keys.contains(index)
+    }
+
+    /// Returns the number of values.
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Returns true if there are no values.
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Returns the set of indices that have a value, for example to combine it with other sets
+    /// through [`BitSetView`](crate::BitSetView).
+    pub fn keys(&self) -> &BitSet<C, LANES> {
+        &self.keys
+    }
+
+    /// Returns an iterator over the indices and their values, in ascending order of the indices.
+    pub fn iter(&self) -> Iter<'_, T, C, LANES> {
+        Iter {
+            blocks: BottomBlocks::new(&self.keys),
+            values: &self.values,
+            base: 0,
+            bits: Bits::default(),
+            block_values: [].iter(),
+        }
+    }
+
+    /// Returns an iterator over the indices and mutable references to their values, in ascending
+    /// order of the indices.
+    pub fn iter_mut(&mut self) -> IterMut<'_, T, C, LANES> {
+        IterMut {
+            blocks: BottomBlocks::new(&self.keys),
+            values: self.values.as_mut_ptr(),
+            base: 0,
+            bits: Bits::default(),
+            block_values: [].iter_mut(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the approximate number of bytes allocated by the map, including the values but not
+    /// anything the values allocate themselves.
+    pub fn memory_usage(&self) -> usize {
+        let values: usize = self
+            .values
+            .iter()
+            .map(|values| values.capacity() * mem::size_of::<T>())
+            .sum();
+        self.keys.memory_usage() + self.values.capacity() * mem::size_of::<Vec<T>>() + values
+    }
+
+    /// Returns the index of the bottom block with the bit at `index`, and the rank of the bit
+    /// within the block, if the bit is set.
+    fn position(&self, index: usize) -> Option<(usize, usize)> {
+        let indices = LevelIndices::<LANES>::new(index);
+
+        unsafe {
+            let top_block = self.keys.top(indices.key)?;
+            let middle_block_index = top_block.get(indices.top)?;
+            let bottom_block_index = self
+                .keys
+                .middle
+                .blocks
+                .get_unchecked(middle_block_index)
+                .get(indices.middle)?;
+            let bits = self
+                .keys
+                .bottom
+                .blocks
+                .get_unchecked(bottom_block_index)
+                .bits;
+            if !bits.get_unchecked(indices.bottom) {
+                return None;
+            }
+            let rank = (bits & Bits::ones_below(indices.bottom)).count_set();
+            Some((bottom_block_index, rank))
+        }
+    }
+}
+
+impl<T: Clone, C: Config, const LANES: usize> Clone for BitMap<T, C, LANES> {
+    fn clone(&self) -> Self {
+        Self {
+            keys: self.keys.clone(),
+            values: self.values.clone(),
+        }
+    }
+}
+
+impl<T, C: Config, const LANES: usize> Default for BitMap<T, C, LANES> {
+    fn default() -> Self {
+        Self {
+            keys: BitSet::default(),
+            values: Vec::new(),
+        }
+    }
+}
+
+impl<T: fmt::Debug, C: Config, const LANES: usize> fmt::Debug for BitMap<T, C, LANES> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<T, C: Config, const LANES: usize> Extend<(usize, T)> for BitMap<T, C, LANES> {
+    fn extend<I: IntoIterator<Item = (usize, T)>>(&mut self, iter: I) {
+        for (index, value) in iter {
+            self.insert(index, value);
+        }
+    }
+}
+
+impl<T, C: Config, const LANES: usize> FromIterator<(usize, T)> for BitMap<T, C, LANES> {
+    fn from_iter<I: IntoIterator<Item = (usize, T)>>(iter: I) -> Self {
+        let mut map = Self::default();
+        map.extend(iter);
+        map
+    }
+}
+
+impl<'a, T, C: Config, const LANES: usize> IntoIterator for &'a BitMap<T, C, LANES> {
+    type Item = (usize, &'a T);
+    type IntoIter = Iter<'a, T, C, LANES>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, T, C: Config, const LANES: usize> IntoIterator for &'a mut BitMap<T, C, LANES> {
+    type Item = (usize, &'a mut T);
+    type IntoIter = IterMut<'a, T, C, LANES>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+/// Iterator over the entries of a [`BitMap`], see [`BitMap::iter`].
+pub struct Iter<'a, T, C: Config = config::Wide, const LANES: usize = 4> {
+    blocks: BottomBlocks<'a, C, LANES>,
+    values: &'a [Vec<T>],
+    /// The first index of the current bottom block.
+    base: usize,
+    /// The not yet visited bits of the current bottom block, and their values.
+    bits: Bits<LANES>,
+    block_values: slice::Iter<'a, T>,
+}
+
+impl<'a, T, C: Config, const LANES: usize> Iterator for Iter<'a, T, C, LANES> {
+    type Item = (usize, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(bit) = self.bits.pop_first_set() {
+                return Some((self.base + bit, self.block_values.next()?));
+            }
+            let (base, bottom_block_index, bits) = self.blocks.next()?;
+            self.base = base;
+            self.bits = bits;
+            self.block_values = self.values[bottom_block_index].iter();
+        }
+    }
+}
+
+impl<T, C: Config, const LANES: usize> FusedIterator for Iter<'_, T, C, LANES> {}
+
+/// Mutable iterator over the entries of a [`BitMap`], see [`BitMap::iter_mut`].
+pub struct IterMut<'a, T, C: Config = config::Wide, const LANES: usize = 4> {
+    blocks: BottomBlocks<'a, C, LANES>,
+    /// The values of the map, only borrowed one bottom block at a time.
+    values: *mut Vec<T>,
+    base: usize,
+    bits: Bits<LANES>,
+    block_values: slice::IterMut<'a, T>,
+    _marker: PhantomData<&'a mut [Vec<T>]>,
+}
+
+unsafe impl<T: Send, C: Config, const LANES: usize> Send for IterMut<'_, T, C, LANES> {}
+unsafe impl<T: Sync, C: Config, const LANES: usize> Sync for IterMut<'_, T, C, LANES> {}
+
+impl<'a, T, C: Config, const LANES: usize> Iterator for IterMut<'a, T, C, LANES> {
+    type Item = (usize, &'a mut T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(bit) = self.bits.pop_first_set() {
+                return Some((self.base + bit, self.block_values.next()?));
+            }
+            let (base, bottom_block_index, bits) = self.blocks.next()?;
+            self.base = base;
+            self.bits = bits;
+            // Every bottom block is linked from exactly one middle block, so each is visited once
+            // and the borrows of their values never overlap.
+            self.block_values = unsafe { (*self.values.add(bottom_block_index)).iter_mut() };
+        }
+    }
+}
+
+impl<T, C: Config, const LANES: usize> FusedIterator for IterMut<'_, T, C, LANES> {}
+
+/// Iterator over the bottom blocks of a [`BitSet`] in ascending order, yielding the first index of
+/// each, its index in the pool and its bits.
+struct BottomBlocks<'a, C: Config, const LANES: usize> {
+    set: &'a BitSet<C, LANES>,
+    next_position: usize,
+    key: usize,
+    top: Bits<LANES>,
+    top_index: usize,
+    middle_block_index: usize,
+    middle: Bits<LANES>,
+}
+
+impl<'a, C: Config, const LANES: usize> BottomBlocks<'a, C, LANES> {
+    fn new(set: &'a BitSet<C, LANES>) -> Self {
+        Self {
+            set,
+            next_position: 0,
+            key: 0,
+            top: Bits::default(),
+            top_index: 0,
+            middle_block_index: 0,
+            middle: Bits::default(),
+        }
+    }
+}
+
+impl<C: Config, const LANES: usize> Iterator for BottomBlocks<'_, C, LANES> {
+    type Item = (usize, usize, Bits<LANES>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        unsafe {
+            loop {
+                if let Some(middle) = self.middle.pop_first_set() {
+                    let bottom_block_index = self
+                        .set
+                        .middle
+                        .blocks
+                        .get_unchecked(self.middle_block_index)
+                        .child_unchecked(middle);
+                    let bits = self
+                        .set
+                        .bottom
+                        .blocks
+                        .get_unchecked(bottom_block_index)
+                        .bits;
+                    let base = LevelIndices::<LANES>::bottom_base(self.key, self.top_index, middle);
+                    return Some((base, bottom_block_index, bits));
+                }
+                if let Some(top) = self.top.pop_first_set() {
+                    let (_, top_block) = self.set.top_at(self.next_position - 1);
+                    self.top_index = top;
+                    self.middle_block_index = top_block.child_unchecked(top);
+                    self.middle = self
+                        .set
+                        .middle
+                        .blocks
+                        .get_unchecked(self.middle_block_index)
+                        .bits;
+                    continue;
+                }
+                if self.next_position == self.set.tops.len() {
+                    return None;
+                }
+                let (key, top_block) = self.set.top_at(self.next_position);
+                self.next_position += 1;
+                self.key = key;
+                self.top = top_block.bits;
+            }
+        }
+    }
+}
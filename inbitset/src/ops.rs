@@ -0,0 +1,277 @@
+//! Lazy set operations over [`BitSetView`]s.
+//!
+//! The views compute the resulting blocks on demand, combining the `Bits` of the operands a whole
+//! block at a time, and prune whole subtrees at the top and middle levels whenever the operation
+//! allows it.
+
+use std::iter;
+
+use crate::{BitSetView, Bits};
+
+/// A binary operation on bitsets.
+pub trait Op: Copy {
+    /// Combines the next top block keys of two operands, as returned by
+    /// [`BitSetView::next_top`]. The result must not be greater than any key of a top block that
+    /// could be non-empty in the combined set.
+    fn next_top(&self, a: Option<usize>, b: Option<usize>) -> Option<usize>;
+
+    /// Combines the previous top block keys of two operands, as returned by
+    /// [`BitSetView::prev_top`]. The result must not be less than any key of a top block that
+    /// could be non-empty in the combined set.
+    fn prev_top(&self, a: Option<usize>, b: Option<usize>) -> Option<usize>;
+
+    /// Combines two bottom blocks.
+    fn bottom<const L: usize>(&self, a: Bits<L>, b: Bits<L>) -> Bits<L>;
+
+    /// Combines the occupancy bits of two top or middle blocks. The result must have every bit set
+    /// whose subtree could be non-empty in the combined set.
+    fn hierarchy<const L: usize>(&self, a: Bits<L>, b: Bits<L>) -> Bits<L>;
+}
+
+/// Intersection.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct And;
+
+impl Op for And {
+    fn next_top(&self, a: Option<usize>, b: Option<usize>) -> Option<usize> {
+        // May point at a key only one of them has, then its top bits are just empty.
+        Some(a?.max(b?))
+    }
+
+    fn prev_top(&self, a: Option<usize>, b: Option<usize>) -> Option<usize> {
+        Some(a?.min(b?))
+    }
+
+    fn bottom<const L: usize>(&self, a: Bits<L>, b: Bits<L>) -> Bits<L> {
+        a & b
+    }
+
+    fn hierarchy<const L: usize>(&self, a: Bits<L>, b: Bits<L>) -> Bits<L> {
+        a & b
+    }
+}
+
+/// Union.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct Or;
+
+impl Op for Or {
+    fn next_top(&self, a: Option<usize>, b: Option<usize>) -> Option<usize> {
+        a.into_iter().chain(b).min()
+    }
+
+    fn prev_top(&self, a: Option<usize>, b: Option<usize>) -> Option<usize> {
+        a.into_iter().chain(b).max()
+    }
+
+    fn bottom<const L: usize>(&self, a: Bits<L>, b: Bits<L>) -> Bits<L> {
+        a | b
+    }
+
+    fn hierarchy<const L: usize>(&self, a: Bits<L>, b: Bits<L>) -> Bits<L> {
+        a | b
+    }
+}
+
+/// Symmetric difference.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct Xor;
+
+impl Op for Xor {
+    fn next_top(&self, a: Option<usize>, b: Option<usize>) -> Option<usize> {
+        a.into_iter().chain(b).min()
+    }
+
+    fn prev_top(&self, a: Option<usize>, b: Option<usize>) -> Option<usize> {
+        a.into_iter().chain(b).max()
+    }
+
+    fn bottom<const L: usize>(&self, a: Bits<L>, b: Bits<L>) -> Bits<L> {
+        a ^ b
+    }
+
+    fn hierarchy<const L: usize>(&self, a: Bits<L>, b: Bits<L>) -> Bits<L> {
+        // Subtrees present in both operands may still differ further down.
+        a | b
+    }
+}
+
+/// Difference, the bits of the first operand that are not in the second.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct AndNot;
+
+impl Op for AndNot {
+    fn next_top(&self, a: Option<usize>, _b: Option<usize>) -> Option<usize> {
+        a
+    }
+
+    fn prev_top(&self, a: Option<usize>, _b: Option<usize>) -> Option<usize> {
+        a
+    }
+
+    fn bottom<const L: usize>(&self, a: Bits<L>, b: Bits<L>) -> Bits<L> {
+        a & !b
+    }
+
+    fn hierarchy<const L: usize>(&self, a: Bits<L>, _b: Bits<L>) -> Bits<L> {
+        // A subtree present in `b` does not have to cover the whole subtree of `a`.
+        a
+    }
+}
+
+/// Lazy result of applying `op` to two bitsets, see [`apply`].
+#[derive(Clone)]
+pub struct Apply<O, A, B> {
+    op: O,
+    a: A,
+    b: B,
+}
+
+impl<O, A, B> Apply<O, A, B> {
+    pub fn new(op: O, a: A, b: B) -> Self {
+        Self { op, a, b }
+    }
+}
+
+/// Handles are the handles of both operands.
+impl<O, A, B, const LANES: usize> BitSetView<LANES> for Apply<O, A, B>
+where
+    O: Op,
+    A: BitSetView<LANES>,
+    B: BitSetView<LANES>,
+{
+    type Top = (A::Top, B::Top);
+    type Middle = (A::Middle, B::Middle);
+
+    fn next_top(&self, key: usize) -> Option<usize> {
+        self.op.next_top(self.a.next_top(key), self.b.next_top(key))
+    }
+
+    fn prev_top(&self, key: usize) -> Option<usize> {
+        self.op.prev_top(self.a.prev_top(key), self.b.prev_top(key))
+    }
+
+    fn enter_top(&self, key: usize) -> (Self::Top, Bits<LANES>) {
+        let (a, a_bits) = self.a.enter_top(key);
+        let (b, b_bits) = self.b.enter_top(key);
+        ((a, b), self.op.hierarchy(a_bits, b_bits))
+    }
+
+    fn enter_middle(&self, (a, b): &Self::Top, top: usize) -> (Self::Middle, Bits<LANES>) {
+        let (a, a_bits) = self.a.enter_middle(a, top);
+        let (b, b_bits) = self.b.enter_middle(b, top);
+        ((a, b), self.op.hierarchy(a_bits, b_bits))
+    }
+
+    fn enter_bottom(&self, (a, b): &Self::Middle, middle: usize) -> Bits<LANES> {
+        self.op.bottom(
+            self.a.enter_bottom(a, middle),
+            self.b.enter_bottom(b, middle),
+        )
+    }
+}
+
+/// Lazy result of folding a sequence of bitsets with `op`, see [`reduce`].
+#[derive(Clone)]
+pub struct Reduce<O, I> {
+    op: O,
+    sets: I,
+}
+
+impl<O, I> Reduce<O, I> {
+    pub fn new(op: O, sets: I) -> Self {
+        Self { op, sets }
+    }
+}
+
+impl<O, I> Reduce<O, I>
+where
+    I: Iterator + Clone,
+{
+    fn fold<T>(&self, f: impl Fn(&I::Item) -> T, op: impl Fn(T, T) -> T) -> Option<T> {
+        let mut sets = self.sets.clone();
+        let first = f(&sets.next()?);
+        Some(sets.fold(first, |acc, set| op(acc, f(&set))))
+    }
+
+    /// Enters a block of every set, passing each set its item of `parents`, and combines the bits
+    /// of the blocks with `op`. Returns the handles of the blocks, in the order of the sets.
+    fn enter<P, H, const LANES: usize>(
+        &self,
+        parents: impl IntoIterator<Item = P>,
+        enter: impl Fn(&I::Item, P) -> (H, Bits<LANES>),
+        op: impl Fn(Bits<LANES>, Bits<LANES>) -> Bits<LANES>,
+    ) -> (Vec<H>, Bits<LANES>) {
+        let mut handles = Vec::new();
+        let mut bits = None;
+        for (set, parent) in self.sets.clone().zip(parents) {
+            let (handle, set_bits) = enter(&set, parent);
+            handles.push(handle);
+            bits = Some(bits.map_or(set_bits, |bits| op(bits, set_bits)));
+        }
+        (handles, bits.unwrap_or_default())
+    }
+}
+
+/// Handles are the handles of all sets, in order.
+impl<O, I, const LANES: usize> BitSetView<LANES> for Reduce<O, I>
+where
+    O: Op,
+    I: Iterator<Item: BitSetView<LANES>> + Clone,
+{
+    type Top = Vec<<I::Item as BitSetView<LANES>>::Top>;
+    type Middle = Vec<<I::Item as BitSetView<LANES>>::Middle>;
+
+    fn next_top(&self, key: usize) -> Option<usize> {
+        self.fold(|set| set.next_top(key), |a, b| self.op.next_top(a, b))?
+    }
+
+    fn prev_top(&self, key: usize) -> Option<usize> {
+        self.fold(|set| set.prev_top(key), |a, b| self.op.prev_top(a, b))?
+    }
+
+    fn enter_top(&self, key: usize) -> (Self::Top, Bits<LANES>) {
+        self.enter(
+            iter::repeat(()),
+            |set, ()| set.enter_top(key),
+            |a, b| self.op.hierarchy(a, b),
+        )
+    }
+
+    fn enter_middle(&self, parents: &Self::Top, top: usize) -> (Self::Middle, Bits<LANES>) {
+        self.enter(
+            parents,
+            |set, parent| set.enter_middle(parent, top),
+            |a, b| self.op.hierarchy(a, b),
+        )
+    }
+
+    fn enter_bottom(&self, parents: &Self::Middle, middle: usize) -> Bits<LANES> {
+        let (_, bits) = self.enter(
+            parents,
+            |set, parent| ((), set.enter_bottom(parent, middle)),
+            |a, b| self.op.bottom(a, b),
+        );
+        bits
+    }
+}
+
+/// Returns a lazy view of `op` applied to `a` and `b`.
+pub fn apply<O, A, B, const LANES: usize>(op: O, a: A, b: B) -> Apply<O, A, B>
+where
+    O: Op,
+    A: BitSetView<LANES>,
+    B: BitSetView<LANES>,
+{
+    Apply::new(op, a, b)
+}
+
+/// Returns a lazy view of `op` folded over all `sets`, from left to right. The iterator is cloned
+/// for every block access, so it should be cheap to clone, like a slice iterator.
+pub fn reduce<O, I, const LANES: usize>(op: O, sets: I) -> Reduce<O, I::IntoIter>
+where
+    O: Op,
+    I: IntoIterator<Item: BitSetView<LANES>, IntoIter: Clone>,
+{
+    Reduce::new(op, sets.into_iter())
+}
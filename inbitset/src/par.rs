@@ -0,0 +1,228 @@
+//! Parallel iteration with [`rayon`].
+
+use rayon::iter::{
+    ParallelIterator,
+    plumbing::{Folder, UnindexedConsumer, UnindexedProducer, bridge_unindexed},
+};
+
+use crate::{BitSetView, Bits, LevelIndices};
+
+/// Parallel iterator over the set bits of a [`BitSetView`].
+///
+/// The work is split along the hierarchy, first between top blocks, then between the middle blocks
+/// of one top block and at last between the bottom blocks of one middle block. Top blocks are split
+/// by halving the range of their keys, so a set spread over many keys splits in logarithmically
+/// many steps. Within a block, each split divides the occupied children in two halves, so the
+/// parts have about the same number of bottom blocks to visit however the bits are clustered.
+/// rayon only splits as long as there are idle threads.
+///
+/// Items are produced in ascending order within each part, but the parts run concurrently.
+pub struct ParIter<'a, S: ?Sized, const LANES: usize = 4> {
+    set: &'a S,
+}
+
+impl<'a, S: ?Sized, const LANES: usize> ParIter<'a, S, LANES> {
+    pub fn new(set: &'a S) -> Self {
+        Self { set }
+    }
+}
+
+impl<S, const LANES: usize> ParallelIterator for ParIter<'_, S, LANES>
+where
+    S: BitSetView<LANES> + Sync + ?Sized,
+{
+    type Item = usize;
+
+    fn drive_unindexed<C>(self, consumer: C) -> C::Result
+    where
+        C: UnindexedConsumer<Self::Item>,
+    {
+        let producer = Producer {
+            set: self.set,
+            part: Part::Keys {
+                from: 0,
+                to: usize::MAX,
+            },
+        };
+        bridge_unindexed(producer, consumer)
+    }
+}
+
+/// Part of a set, the remaining work of one producer.
+#[derive(Copy, Clone)]
+enum Part<const LANES: usize> {
+    /// The top blocks with keys from `from` to `to`, both inclusive.
+    Keys { from: usize, to: usize },
+    /// The `tops` subtrees of the top block with `key`.
+    Top { key: usize, tops: Bits<LANES> },
+    /// The `middles` subtrees of the middle block under the `top` bit of the top block with `key`.
+    Middle {
+        key: usize,
+        top: usize,
+        middles: Bits<LANES>,
+    },
+}
+
+struct Producer<'a, S: ?Sized, const LANES: usize> {
+    set: &'a S,
+    part: Part<LANES>,
+}
+
+impl<S, const LANES: usize> Producer<'_, S, LANES>
+where
+    S: BitSetView<LANES> + ?Sized,
+{
+    fn with_part(&self, part: Part<LANES>) -> Self {
+        Self {
+            set: self.set,
+            part,
+        }
+    }
+
+    /// Returns the smallest key of a top block in `from..=to` that may be non-empty.
+    fn next_top(&self, from: usize, to: usize) -> Option<usize> {
+        self.set.next_top(from).filter(|&key| key <= to)
+    }
+
+    /// Returns the largest key of a top block in `from..=to` that may be non-empty.
+    fn prev_top(&self, from: usize, to: usize) -> Option<usize> {
+        self.set.prev_top(to).filter(|&key| key >= from)
+    }
+
+    fn fold_top<F: Folder<usize>>(
+        &self,
+        mut folder: F,
+        key: usize,
+        block: &S::Top,
+        mut tops: Bits<LANES>,
+    ) -> F {
+        while let Some(top) = tops.pop_first_set() {
+            let (middle_block, middles) = self.set.enter_middle(block, top);
+            folder = self.fold_middle(folder, key, top, &middle_block, middles);
+            if folder.full() {
+                break;
+            }
+        }
+        folder
+    }
+
+    fn fold_middle<F: Folder<usize>>(
+        &self,
+        mut folder: F,
+        key: usize,
+        top: usize,
+        block: &S::Middle,
+        mut middles: Bits<LANES>,
+    ) -> F {
+        while let Some(middle) = middles.pop_first_set() {
+            let base = LevelIndices::<LANES>::bottom_base(key, top, middle);
+            let mut bottoms = self.set.enter_bottom(block, middle);
+            while let Some(bottom) = bottoms.pop_first_set() {
+                folder = folder.consume(base + bottom);
+            }
+            if folder.full() {
+                break;
+            }
+        }
+        folder
+    }
+}
+
+impl<S, const LANES: usize> UnindexedProducer for Producer<'_, S, LANES>
+where
+    S: BitSetView<LANES> + Sync + ?Sized,
+{
+    type Item = usize;
+
+    fn split(self) -> (Self, Option<Self>) {
+        match self.part {
+            Part::Keys { from, to } => {
+                let (Some(first), Some(last)) = (self.next_top(from, to), self.prev_top(from, to))
+                else {
+                    return (self, None);
+                };
+                if first == last {
+                    // A single top block left, split its subtrees instead.
+                    return self
+                        .with_part(Part::Top {
+                            key: first,
+                            tops: self.set.top_bits(first),
+                        })
+                        .split();
+                }
+                // Both halves are non-empty, the lower one has `first` and the upper one `last`.
+                let mid = first + (last - first) / 2;
+                (
+                    self.with_part(Part::Keys {
+                        from: first,
+                        to: mid,
+                    }),
+                    Some(self.with_part(Part::Keys {
+                        from: mid + 1,
+                        to: last,
+                    })),
+                )
+            }
+            Part::Top { key, tops } => match tops.count_set() {
+                0 => (self, None),
+                1 => {
+                    let top = tops.first_set().unwrap();
+                    let middles = self.set.middle_bits(key, top);
+                    self.with_part(Part::Middle { key, top, middles }).split()
+                }
+                _ => {
+                    let (lower, upper) = tops.split_half();
+                    (
+                        self.with_part(Part::Top { key, tops: lower }),
+                        Some(self.with_part(Part::Top { key, tops: upper })),
+                    )
+                }
+            },
+            Part::Middle { key, top, middles } => {
+                if middles.count_set() < 2 {
+                    return (self, None);
+                }
+                let (lower, upper) = middles.split_half();
+                (
+                    self.with_part(Part::Middle {
+                        key,
+                        top,
+                        middles: lower,
+                    }),
+                    Some(self.with_part(Part::Middle {
+                        key,
+                        top,
+                        middles: upper,
+                    })),
+                )
+            }
+        }
+    }
+
+    fn fold_with<F: Folder<usize>>(self, mut folder: F) -> F {
+        match self.part {
+            Part::Keys { from, to } => {
+                let mut next = Some(from);
+                while let Some(key) = next.and_then(|next| self.next_top(next, to)) {
+                    let (block, tops) = self.set.enter_top(key);
+                    folder = self.fold_top(folder, key, &block, tops);
+                    if folder.full() {
+                        break;
+                    }
+                    next = key.checked_add(1);
+                }
+                folder
+            }
+            // Parts only keep the path, the blocks are looked up once for the whole part.
+            Part::Top { key, tops } => {
+                let (block, _) = self.set.enter_top(key);
+                self.fold_top(folder, key, &block, tops)
+            }
+            Part::Middle { key, top, middles } => {
+                let (top_block, _) = self.set.enter_top(key);
+                let (block, _) = self.set.enter_middle(&top_block, top);
+                self.fold_middle(folder, key, top, &block, middles)
+            }
+        }
+    }
+}
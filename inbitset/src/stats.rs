@@ -0,0 +1,85 @@
+use std::mem;
+
+use crate::{BitSet, Bits, Config, TopEntry};
+
+/// Statistics about the blocks of a [`BitSet`], see [`BitSet::stats`].
+#[derive(Copy, Clone, PartialEq, Default, Debug)]
+pub struct Stats {
+    /// Number of set bits.
+    pub len: usize,
+    pub top: LevelStats,
+    pub middle: LevelStats,
+    pub bottom: LevelStats,
+    /// Number of bytes used by the whole set, as returned by [`BitSet::memory_usage`].
+    pub bytes: usize,
+}
+
+/// Statistics about one level of the hierarchy of a [`BitSet`].
+#[derive(Copy, Clone, PartialEq, Default, Debug)]
+pub struct LevelStats {
+    /// Number of blocks in use.
+    pub blocks: usize,
+    /// Number of blocks on the free list, waiting to be reused.
+    pub free: usize,
+    /// Fraction of the bits of the blocks in use that are set, from 0 to 1. For the top and middle
+    /// levels, that is the fraction of the possible children that exist.
+    pub fill_ratio: f64,
+    /// Number of bytes allocated for the blocks of the level, including the free ones and unused
+    /// capacity. For the top level, that includes the list of keys.
+    pub bytes: usize,
+}
+
+impl LevelStats {
+    fn new<const LANES: usize>(blocks: usize, free: usize, set: usize, bytes: usize) -> Self {
+        let fill_ratio = match blocks {
+            0 => 0.0,
+            blocks => set as f64 / (blocks * Bits::<LANES>::SIZE) as f64,
+        };
+        Self {
+            blocks,
+            free,
+            fill_ratio,
+            bytes,
+        }
+    }
+}
+
+impl<C: Config, const LANES: usize> BitSet<C, LANES> {
+    /// Returns statistics about the blocks of every level, to judge how well the block geometry
+    /// and config fit the data. Visits the top and middle blocks in use.
+    pub fn stats(&self) -> Stats {
+        let middles: usize = self
+            .top_blocks()
+            .map(|(_, top_block)| top_block.bits.count_set())
+            .sum();
+        let mut bottoms = 0;
+        for (_, top_block) in self.top_blocks() {
+            top_block.bits.for_each_set(|top| {
+                let middle_block_index = unsafe { top_block.child_unchecked(top) };
+                bottoms += self.middle.blocks[middle_block_index].bits.count_set();
+            });
+        }
+
+        let tops = self.tops.len();
+        let top_bytes = self.tops.capacity() * mem::size_of::<TopEntry>()
+            + self.counts.memory_usage()
+            + self.top.memory_usage();
+        Stats {
+            len: self.len,
+            top: LevelStats::new::<LANES>(tops, self.top.blocks.len() - tops, middles, top_bytes),
+            middle: LevelStats::new::<LANES>(
+                middles,
+                self.middle.blocks.len() - middles,
+                bottoms,
+                self.middle.memory_usage(),
+            ),
+            bottom: LevelStats::new::<LANES>(
+                bottoms,
+                self.bottom.blocks.len() - bottoms,
+                self.len,
+                self.bottom.memory_usage(),
+            ),
+            bytes: self.memory_usage(),
+        }
+    }
+}
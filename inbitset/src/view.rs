@@ -0,0 +1,250 @@
+use std::ops::RangeBounds;
+
+#[cfg(feature = "rayon")]
+use crate::ParIter;
+use crate::{
+    BitSet, Bits, Config, Iter, LevelIndices, config,
+    ops::{And, AndNot, Apply, Or, Xor},
+};
+
+/// Read-only access to a hierarchical bitset, level by level.
+///
+/// Top blocks are addressed by their key, and the blocks below them by the path of bit indices
+/// leading to them from the top. Every bit of the top and middle levels stands for a block below
+/// it, and a set bit may only be left unset in the returned bits if the whole subtree below it is
+/// empty. This lets iteration skip empty subtrees without materializing anything, which is what
+/// the lazy views in [`crate::ops`] build on.
+///
+/// Walking the hierarchy goes through handles rather than paths: [`Self::enter_top`] looks a top
+/// block up once, and each block below is found from the handle of its parent, so a step down
+/// costs the same however the view finds its top blocks. The path-based accessors are built on the
+/// handles, for random access. A handle only means something to the view that returned it, any
+/// other gives unspecified bits.
+///
+/// The block accessors panic if a bit index in the path is not less than `Bits::SIZE`. `LANES` is
+/// the block geometry of the view, see [`Bits`].
+pub trait BitSetView<const LANES: usize = 4> {
+    /// Handle to a top block, see [`Self::enter_top`]. The default handle stands for a missing
+    /// block.
+    type Top: Clone + Default;
+
+    /// Handle to a middle block, see [`Self::enter_middle`]. The default handle stands for a
+    /// missing block.
+    type Middle: Clone + Default;
+
+    /// Returns the smallest key, not less than `key`, of a top block that may be non-empty.
+    fn next_top(&self, key: usize) -> Option<usize>;
+
+    /// Returns the largest key, not greater than `key`, of a top block that may be non-empty.
+    fn prev_top(&self, key: usize) -> Option<usize>;
+
+    /// Returns a handle to the top block with `key` and its occupancy bits, or empty bits if there
+    /// is no such block.
+    fn enter_top(&self, key: usize) -> (Self::Top, Bits<LANES>);
+
+    /// Returns a handle to the middle block under the `top` bit of the `parent` top block and its
+    /// occupancy bits, or empty bits if there is no such block.
+    fn enter_middle(&self, parent: &Self::Top, top: usize) -> (Self::Middle, Bits<LANES>);
+
+    /// Returns the bits of the bottom block under the `middle` bit of the `parent` middle block, or
+    /// empty bits if there is no such block.
+    fn enter_bottom(&self, parent: &Self::Middle, middle: usize) -> Bits<LANES>;
+
+    /// Returns the occupancy bits of the top block with `key`, or empty bits if there is no such
+    /// block.
+    fn top_bits(&self, key: usize) -> Bits<LANES> {
+        self.enter_top(key).1
+    }
+
+    /// Returns the occupancy bits of the middle block under the `top` bit, or empty bits if there
+    /// is no such block.
+    fn middle_bits(&self, key: usize, top: usize) -> Bits<LANES> {
+        self.enter_middle(&self.enter_top(key).0, top).1
+    }
+
+    /// Returns the bits of the bottom block under the `top` and `middle` bits, or empty bits if
+    /// there is no such block.
+    fn bottom_bits(&self, key: usize, top: usize, middle: usize) -> Bits<LANES> {
+        let (middle_block, _) = self.enter_middle(&self.enter_top(key).0, top);
+        self.enter_bottom(&middle_block, middle)
+    }
+
+    /// Returns true if the bit at `bit_index` is set.
+    fn contains(&self, bit_index: usize) -> bool {
+        let indices = LevelIndices::<LANES>::new(bit_index);
+
+        unsafe {
+            self.bottom_bits(indices.key, indices.top, indices.middle)
+                .get_unchecked(indices.bottom)
+        }
+    }
+
+    /// Returns an iterator over the set bits, in ascending order. Use [`Iter::new`] to iterate
+    /// over an owned view.
+    fn iter(&self) -> Iter<&Self, LANES> {
+        Iter::new(self)
+    }
+
+    /// Returns an iterator over the set bits in `range`, in ascending order. Use
+    /// [`Iter::with_range`] to iterate over a range of an owned view.
+    fn iter_range(&self, range: impl RangeBounds<usize>) -> Iter<&Self, LANES> {
+        Iter::with_range(self, range)
+    }
+
+    /// Returns a parallel iterator over the set bits, see [`ParIter`].
+    #[cfg(feature = "rayon")]
+    fn par_iter(&self) -> ParIter<'_, Self, LANES>
+    where
+        Self: Sync,
+    {
+        ParIter::new(self)
+    }
+
+    /// Collects the set bits into a new [`BitSet`] of the same geometry, block by block.
+    fn materialize(&self) -> BitSet<config::Wide, LANES> {
+        let mut set = BitSet::default();
+        let mut next_key = 0;
+        while let Some(key) = self.next_top(next_key) {
+            let (top_block, top_bits) = self.enter_top(key);
+            top_bits.for_each_set(|top| {
+                let (middle_block, middle_bits) = self.enter_middle(&top_block, top);
+                middle_bits.for_each_set(|middle| {
+                    let bits = self.enter_bottom(&middle_block, middle);
+                    if !bits.is_empty() {
+                        unsafe { set.insert_bits(key, top, middle, bits) };
+                    }
+                });
+            });
+            next_key = key + 1;
+        }
+        set
+    }
+
+    /// Returns a lazy intersection of `self` and `other`.
+    fn and<B: BitSetView<LANES>>(self, other: B) -> Apply<And, Self, B>
+    where
+        Self: Sized,
+    {
+        Apply::new(And, self, other)
+    }
+
+    /// Returns a lazy union of `self` and `other`.
+    fn or<B: BitSetView<LANES>>(self, other: B) -> Apply<Or, Self, B>
+    where
+        Self: Sized,
+    {
+        Apply::new(Or, self, other)
+    }
+
+    /// Returns a lazy symmetric difference of `self` and `other`.
+    fn xor<B: BitSetView<LANES>>(self, other: B) -> Apply<Xor, Self, B>
+    where
+        Self: Sized,
+    {
+        Apply::new(Xor, self, other)
+    }
+
+    /// Returns a lazy difference of `self` and `other`.
+    fn and_not<B: BitSetView<LANES>>(self, other: B) -> Apply<AndNot, Self, B>
+    where
+        Self: Sized,
+    {
+        Apply::new(AndNot, self, other)
+    }
+}
+
+impl<T: BitSetView<LANES> + ?Sized, const LANES: usize> BitSetView<LANES> for &T {
+    type Top = T::Top;
+    type Middle = T::Middle;
+
+    fn next_top(&self, key: usize) -> Option<usize> {
+        (**self).next_top(key)
+    }
+
+    fn prev_top(&self, key: usize) -> Option<usize> {
+        (**self).prev_top(key)
+    }
+
+    fn enter_top(&self, key: usize) -> (Self::Top, Bits<LANES>) {
+        (**self).enter_top(key)
+    }
+
+    fn enter_middle(&self, parent: &Self::Top, top: usize) -> (Self::Middle, Bits<LANES>) {
+        (**self).enter_middle(parent, top)
+    }
+
+    fn enter_bottom(&self, parent: &Self::Middle, middle: usize) -> Bits<LANES> {
+        (**self).enter_bottom(parent, middle)
+    }
+
+    fn top_bits(&self, key: usize) -> Bits<LANES> {
+        (**self).top_bits(key)
+    }
+
+    fn middle_bits(&self, key: usize, top: usize) -> Bits<LANES> {
+        (**self).middle_bits(key, top)
+    }
+
+    fn bottom_bits(&self, key: usize, top: usize, middle: usize) -> Bits<LANES> {
+        (**self).bottom_bits(key, top, middle)
+    }
+}
+
+/// Handles are the indices of the blocks in their pools.
+impl<C: Config, const LANES: usize> BitSetView<LANES> for BitSet<C, LANES> {
+    type Top = Option<usize>;
+    type Middle = Option<usize>;
+
+    fn next_top(&self, key: usize) -> Option<usize> {
+        let position = self.tops.partition_point(|top| top.key < key);
+        self.tops.get(position).map(|top| top.key)
+    }
+
+    fn prev_top(&self, key: usize) -> Option<usize> {
+        let position = self.tops.partition_point(|top| top.key <= key);
+        Some(self.tops.get(position.checked_sub(1)?)?.key)
+    }
+
+    fn enter_top(&self, key: usize) -> (Self::Top, Bits<LANES>) {
+        let Ok(position) = self.top_position(key) else {
+            return (None, Bits::default());
+        };
+        unsafe {
+            let block = self.tops.get_unchecked(position).block;
+            (Some(block), self.top.blocks.get_unchecked(block).bits)
+        }
+    }
+
+    fn enter_middle(&self, parent: &Self::Top, top: usize) -> (Self::Middle, Bits<LANES>) {
+        assert!(top < Bits::<LANES>::SIZE, "top index out of range");
+
+        // Handles come from the caller, so only the indices stored in blocks are trusted.
+        let Some(top_block) = parent.and_then(|block| self.top.blocks.get(block)) else {
+            return (None, Bits::default());
+        };
+        unsafe {
+            match top_block.get(top) {
+                Some(block) => (Some(block), self.middle.blocks.get_unchecked(block).bits),
+                None => (None, Bits::default()),
+            }
+        }
+    }
+
+    fn enter_bottom(&self, parent: &Self::Middle, middle: usize) -> Bits<LANES> {
+        assert!(middle < Bits::<LANES>::SIZE, "middle index out of range");
+
+        let Some(middle_block) = parent.and_then(|block| self.middle.blocks.get(block)) else {
+            return Bits::default();
+        };
+        unsafe {
+            match middle_block.get(middle) {
+                Some(block) => self.bottom.blocks.get_unchecked(block).bits,
+                None => Bits::default(),
+            }
+        }
+    }
+
+    fn contains(&self, bit_index: usize) -> bool {
+        BitSet::contains(self, bit_index)
+    }
+}
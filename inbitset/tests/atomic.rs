@@ -0,0 +1,83 @@
+//! Concurrent inserts and removes on an `AtomicBitSet`, checked against the same operations done
+//! sequentially on a `BitSet`.
+
+use std::thread;
+
+use inbitset::{AtomicBitSet, BitSet, BitSetView};
+
+const CAPACITY: usize = 300_000;
+const THREADS: usize = 8;
+
+/// Indices touched by `thread`, overlapping with the ones of the other threads.
+fn indices(thread: usize) -> impl Iterator<Item = usize> {
+    (thread * 1000..CAPACITY).step_by(THREADS + thread)
+}
+
+#[test]
+fn concurrent_inserts_and_removes() {
+    let set: AtomicBitSet = AtomicBitSet::with_capacity(CAPACITY);
+    thread::scope(|scope| {
+        for thread in 0..THREADS {
+            let set = &set;
+            scope.spawn(move || {
+                for index in indices(thread) {
+                    set.insert(index);
+                }
+            });
+        }
+    });
+
+    let mut expected = BitSet::new();
+    for thread in 0..THREADS {
+        expected.extend(indices(thread));
+    }
+    assert!(set.iter().eq(expected.iter()));
+
+    thread::scope(|scope| {
+        for thread in (0..THREADS).step_by(2) {
+            let set = &set;
+            scope.spawn(move || {
+                for index in indices(thread) {
+                    set.remove(index);
+                }
+            });
+        }
+    });
+    for thread in (0..THREADS).step_by(2) {
+        for index in indices(thread) {
+            expected.remove(index);
+        }
+    }
+    assert!(set.iter().eq(expected.iter()));
+    assert!(set.iter().rev().eq(expected.iter().rev()));
+    assert!((&set).and(&expected).iter().eq(expected.iter()));
+
+    let set = BitSet::from(set);
+    assert_eq!(set, expected);
+    assert_eq!(set.len(), expected.len());
+}
+
+#[test]
+fn bounds_and_clear() {
+    let mut set: AtomicBitSet = AtomicBitSet::with_capacity(1 << 25);
+    assert!(set.insert(0));
+    assert!(!set.insert(0));
+    assert!(set.insert((1 << 25) - 1));
+    assert!(set.contains((1 << 25) - 1));
+    assert!(!set.contains(1 << 25));
+    assert!(!set.remove(1 << 25));
+    assert!(set.iter().eq([0, (1 << 25) - 1]));
+
+    set.clear();
+    assert_eq!(set.iter().next(), None);
+    assert_eq!(set.next_top(0), None);
+    assert!(set.insert(12345));
+    assert!(set.iter().eq([12345]));
+}
+
+#[test]
+#[should_panic(expected = "bit index out of capacity")]
+fn insert_out_of_capacity() {
+    let set: AtomicBitSet = AtomicBitSet::with_capacity(100);
+    set.insert(100);
+}
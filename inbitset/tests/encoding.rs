@@ -0,0 +1,74 @@
+//! Validation of encoded sets and the serde impls.
+
+use inbitset::{BitSet, BitSetRef, BitSetView, DecodeError, config::Wide};
+
+fn sample() -> BitSet {
+    let mut set = BitSet::new();
+    set.insert_range(100..5000);
+    set.extend([1 << 20, 1 << 40, usize::MAX]);
+    set
+}
+
+#[test]
+fn queries_in_place() {
+    let set = sample();
+    let bytes = set.to_bytes();
+    let set_ref = BitSetRef::<4>::new(&bytes).unwrap();
+    assert_eq!(set_ref.len(), set.len());
+    assert!(set_ref.contains(1 << 40) && !set_ref.contains(99));
+    assert!(set_ref.iter().eq(set.iter()));
+
+    let empty = BitSet::new().to_bytes();
+    assert!(BitSetRef::<4>::new(&empty).unwrap().is_empty());
+}
+
+#[test]
+fn rejects_invalid_bytes() {
+    let bytes = sample().to_bytes();
+    let decode = |bytes: &[u8]| BitSetRef::<4>::new(bytes).map(|_| ());
+
+    assert_eq!(decode(&bytes[..bytes.len() - 1]), Err(DecodeError::Length));
+    assert_eq!(decode(&bytes[..10]), Err(DecodeError::Length));
+    assert_eq!(
+        BitSetRef::<2>::new(&bytes).map(|_| ()),
+        Err(DecodeError::Lanes(4))
+    );
+
+    let mut corrupt = bytes.clone();
+    corrupt[0] = b'x';
+    assert_eq!(decode(&corrupt), Err(DecodeError::Magic));
+
+    let mut corrupt = bytes.clone();
+    corrupt[4] = 2;
+    assert_eq!(decode(&corrupt), Err(DecodeError::Version(2)));
+
+    // Set one more bit in the last bottom block, without updating the length.
+    let mut corrupt = bytes.clone();
+    let last = corrupt.len() - 1;
+    corrupt[last] ^= 1;
+    assert_eq!(decode(&corrupt), Err(DecodeError::Corrupt));
+
+    // Swap the order of the first two top blocks' keys.
+    let mut corrupt = bytes;
+    corrupt[40..48].copy_from_slice(&u64::MAX.to_le_bytes());
+    assert_eq!(decode(&corrupt), Err(DecodeError::Corrupt));
+
+    // Move the only top block past the end of the index range.
+    let mut corrupt = BitSet::<Wide>::from_iter([5]).to_bytes();
+    corrupt[40..48].copy_from_slice(&(1u64 << 50).to_le_bytes());
+    assert_eq!(decode(&corrupt), Err(DecodeError::Corrupt));
+    assert_eq!(
+        BitSet::<Wide>::from_bytes(&corrupt),
+        Err(DecodeError::Corrupt)
+    );
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_round_trip() {
+    let set = sample();
+    let json = serde_json::to_string(&set).unwrap();
+    let decoded: BitSet = serde_json::from_str(&json).unwrap();
+    assert_eq!(decoded, set);
+    assert!(serde_json::from_str::<BitSet>("[1, 2, 3]").is_err());
+}
@@ -0,0 +1,106 @@
+//! Random sequences of operations on a `BitMap`, checked against a `BTreeMap`.
+
+use std::collections::BTreeMap;
+
+use inbitset::{BitMap, config::Compact};
+use proptest::prelude::*;
+
+#[derive(Clone, Debug)]
+enum Op {
+    Insert(usize, u32),
+    Remove(usize),
+    Get(usize),
+    Update(usize, u32),
+    Iter,
+}
+
+/// Indices in a few clusters, so values get inserted into and removed from the same blocks, and
+/// anywhere in the `usize` range.
+fn index() -> impl Strategy<Value = usize> {
+    prop_oneof![
+        4 => (0..4usize, 0..2048usize).prop_map(|(cluster, offset)| cluster << 24 | offset),
+        1 => any::<usize>(),
+    ]
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        6 => (index(), any::<u32>()).prop_map(|(index, value)| Op::Insert(index, value)),
+        4 => index().prop_map(Op::Remove),
+        2 => index().prop_map(Op::Get),
+        1 => any::<u32>().prop_map(|n| Op::Update(n as usize, n)),
+        1 => Just(Op::Iter),
+    ]
+}
+
+fn check<const LANES: usize>(map: &BitMap<u32, Compact, LANES>, model: &BTreeMap<usize, u32>) {
+    assert!(
+        map.iter()
+            .map(|(i, &v)| (i, v))
+            .eq(model.iter().map(|(&i, &v)| (i, v)))
+    );
+    assert!(map.keys().iter().eq(model.keys().copied()));
+    assert_eq!(map.len(), model.len());
+    assert_eq!(map.is_empty(), model.is_empty());
+}
+
+fn run<const LANES: usize>(ops: Vec<Op>) {
+    let mut map = BitMap::<u32, Compact, LANES>::default();
+    let mut model = BTreeMap::new();
+    for op in ops {
+        match op {
+            Op::Insert(index, value) => {
+                assert_eq!(map.insert(index, value), model.insert(index, value));
+            }
+            Op::Remove(index) => assert_eq!(map.remove(index), model.remove(&index)),
+            Op::Get(index) => {
+                assert_eq!(map.get(index), model.get(&index));
+                assert_eq!(map.contains_key(index), model.contains_key(&index));
+            }
+            Op::Update(n, value) => {
+                // Touch every n-th entry through the mutable iterator.
+                let n = n % 7 + 1;
+                for (_, v) in map.iter_mut().step_by(n) {
+                    *v = value;
+                }
+                for (_, v) in model.iter_mut().step_by(n) {
+                    *v = value;
+                }
+            }
+            Op::Iter => check(&map, &model),
+        }
+    }
+    check(&map, &model);
+}
+
+proptest! {
+    #[test]
+    fn matches_btree_map(ops in prop::collection::vec(op(), 1..400)) {
+        run::<4>(ops);
+    }
+
+    #[test]
+    fn matches_btree_map_64bit(ops in prop::collection::vec(op(), 1..400)) {
+        run::<1>(ops);
+    }
+}
+
+#[test]
+fn reuses_freed_blocks() {
+    let mut map = BitMap::new();
+    for round in 0..4 {
+        map.extend((0..100_000).step_by(7).map(|index| (index, vec![round; 3])));
+        assert_eq!(map.get(700), Some(&vec![round; 3]));
+        for index in (0..100_000).step_by(7) {
+            assert_eq!(map.remove(index), Some(vec![round; 3]));
+        }
+        assert!(map.is_empty());
+    }
+    let full = map.memory_usage();
+    map.extend((0..100_000).step_by(7).map(|index| (index, Vec::new())));
+    assert_eq!(map.memory_usage(), full);
+
+    assert_eq!(map.insert(usize::MAX, vec![1]), None);
+    map.get_mut(usize::MAX).unwrap().push(2);
+    assert_eq!(map.iter().last(), Some((usize::MAX, &vec![1, 2])));
+}
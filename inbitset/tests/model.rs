@@ -0,0 +1,596 @@
+//! Model-based tests, running random sequences of operations against two `BitSet`s and two
+//! `BTreeSet`s side by side and checking that they agree.
+
+use std::collections::BTreeSet;
+
+use inbitset::{
+    BitSet, BitSetRef, BitSetView, Change, CowBitSet,
+    config::{Compact, Wide},
+};
+use proptest::prelude::*;
+
+#[derive(Clone, Debug)]
+enum Op {
+    Insert(usize, usize),
+    Remove(usize, usize),
+    InsertRange(usize, usize, usize),
+    RemoveRange(usize, usize, usize),
+    Extend(usize, Vec<usize>),
+    Contains(usize, usize),
+    Rank(usize, usize),
+    Select(usize, usize),
+    NextAfter(usize, usize),
+    PrevBefore(usize, usize),
+    PopFirst(usize),
+    PopLast(usize),
+    Compact(usize),
+    Retain(usize, usize),
+    Drain(usize, usize),
+    Iter(usize),
+    IterRange(usize, usize, usize),
+    IterBothEnds(usize, Vec<bool>),
+    Snapshot,
+    SetOps,
+}
+
+/// Indices concentrated around the boundaries of blocks with `block_size` bits, in a few small
+/// clusters to make inserts and removes hit the same blocks (and reuse freed ones), and anywhere in
+/// the `usize` range.
+fn index(block_size: usize) -> impl Strategy<Value = usize> {
+    // Number of indices covered by a bottom, middle and top block.
+    let block_caps = [block_size, block_size.pow(2), block_size.pow(3)];
+    let boundary = (0..block_caps.len(), 0..4usize, -2..2isize)
+        .prop_map(move |(level, n, offset)| (block_caps[level] * n).wrapping_add_signed(offset));
+    let cluster = (0..4usize, 0..512usize).prop_map(|(cluster, offset)| cluster * 100_000 + offset);
+    prop_oneof![
+        3 => boundary,
+        3 => cluster,
+        1 => 0..block_caps[2],
+        1 => any::<usize>(),
+        1 => Just(usize::MAX),
+    ]
+}
+
+fn op(block_size: usize) -> impl Strategy<Value = Op> {
+    let side = 0..2usize;
+    let index = || index(block_size);
+    prop_oneof![
+        8 => (side.clone(), index()).prop_map(|(side, index)| Op::Insert(side, index)),
+        6 => (side.clone(), index()).prop_map(|(side, index)| Op::Remove(side, index)),
+        1 => (side.clone(), index(), 0..block_size * 3)
+            .prop_map(|(side, start, len)| Op::InsertRange(side, start, len)),
+        1 => (side.clone(), index(), index())
+            .prop_map(|(side, a, b)| Op::RemoveRange(side, a.min(b), a.max(b))),
+        1 => (side.clone(), prop::collection::vec(index(), 0..64))
+            .prop_map(|(side, indices)| Op::Extend(side, indices)),
+        2 => (side.clone(), index()).prop_map(|(side, index)| Op::Contains(side, index)),
+        1 => (side.clone(), index()).prop_map(|(side, index)| Op::Rank(side, index)),
+        1 => (side.clone(), 0..64usize).prop_map(|(side, n)| Op::Select(side, n)),
+        1 => (side.clone(), index()).prop_map(|(side, index)| Op::NextAfter(side, index)),
+        1 => (side.clone(), index()).prop_map(|(side, index)| Op::PrevBefore(side, index)),
+        1 => side.clone().prop_map(Op::PopFirst),
+        1 => side.clone().prop_map(Op::PopLast),
+        1 => side.clone().prop_map(Op::Compact),
+        1 => (side.clone(), 2..8usize).prop_map(|(side, n)| Op::Retain(side, n)),
+        1 => (side.clone(), 0..64usize).prop_map(|(side, n)| Op::Drain(side, n)),
+        1 => side.clone().prop_map(Op::Iter),
+        1 => (side.clone(), index(), index())
+            .prop_map(|(side, a, b)| Op::IterRange(side, a.min(b), a.max(b))),
+        1 => (side, prop::collection::vec(any::<bool>(), 0..64))
+            .prop_map(|(side, from_back)| Op::IterBothEnds(side, from_back)),
+        1 => Just(Op::Snapshot),
+        1 => Just(Op::SetOps),
+    ]
+}
+
+#[derive(Default)]
+struct Model<const LANES: usize> {
+    sets: [BitSet<Wide, LANES>; 2],
+    models: [BTreeSet<usize>; 2],
+    /// Follows the inserts and removes of the first side.
+    cow: CowBitSet<LANES>,
+    cow_model: BTreeSet<usize>,
+    /// The last few clones of `cow`, which share blocks with it.
+    snapshots: Vec<(CowBitSet<LANES>, BTreeSet<usize>)>,
+}
+
+impl<const LANES: usize> Model<LANES> {
+    fn apply(&mut self, op: Op) {
+        match op {
+            Op::Insert(side, index) => {
+                assert_eq!(
+                    self.sets[side].insert(index),
+                    self.models[side].insert(index)
+                );
+                if side == 0 {
+                    assert_eq!(self.cow.insert(index), self.cow_model.insert(index));
+                }
+            }
+            Op::Remove(side, index) => {
+                assert_eq!(
+                    self.sets[side].remove(index),
+                    self.models[side].remove(&index)
+                );
+                if side == 0 {
+                    assert_eq!(self.cow.remove(index), self.cow_model.remove(&index));
+                }
+            }
+            Op::InsertRange(side, start, len) => {
+                let end = start.saturating_add(len);
+                self.sets[side].insert_range(start..end);
+                self.models[side].extend(start..end);
+            }
+            Op::RemoveRange(side, start, end) => {
+                self.sets[side].remove_range(start..=end);
+                self.models[side].retain(|index| !(start..=end).contains(index));
+            }
+            Op::Extend(side, mut indices) => {
+                if side == 0 {
+                    indices.sort_unstable();
+                }
+                self.sets[side].extend(&indices);
+                self.models[side].extend(&indices);
+            }
+            Op::Contains(side, index) => {
+                assert_eq!(
+                    self.sets[side].contains(index),
+                    self.models[side].contains(&index)
+                );
+            }
+            Op::Rank(side, index) => {
+                let rank = self.models[side].range(..index).count();
+                assert_eq!(self.sets[side].rank(index), rank);
+            }
+            Op::Select(side, n) => {
+                let nth = self.models[side].iter().nth(n).copied();
+                assert_eq!(self.sets[side].select(n), nth);
+            }
+            Op::NextAfter(side, index) => {
+                let next = match index.checked_add(1) {
+                    Some(start) => self.models[side].range(start..).next().copied(),
+                    None => None,
+                };
+                assert_eq!(self.sets[side].next_after(index), next);
+            }
+            Op::PrevBefore(side, index) => {
+                let prev = self.models[side].range(..index).next_back().copied();
+                assert_eq!(self.sets[side].prev_before(index), prev);
+            }
+            Op::PopFirst(side) => {
+                assert_eq!(self.sets[side].pop_first(), self.models[side].pop_first());
+            }
+            Op::PopLast(side) => {
+                assert_eq!(self.sets[side].pop_last(), self.models[side].pop_last());
+            }
+            Op::Compact(side) => {
+                self.sets[side].compact();
+                let stats = self.sets[side].stats();
+                assert_eq!((stats.middle.free, stats.bottom.free), (0, 0));
+            }
+            Op::Retain(side, n) => {
+                let mut visited = Vec::new();
+                self.sets[side].retain(|index| {
+                    visited.push(index);
+                    index % n != 0
+                });
+                assert!(visited.iter().eq(self.models[side].iter()));
+                self.models[side].retain(|index| index % n != 0);
+            }
+            Op::Drain(side, n) => {
+                let drained: Vec<_> = self.sets[side].drain().take(n).collect();
+                assert!(drained.iter().eq(self.models[side].iter().take(n)));
+                assert!(self.sets[side].is_empty());
+                self.models[side].clear();
+            }
+            Op::Iter(side) => {
+                let set = &self.sets[side];
+                let model = &self.models[side];
+                assert!(set.iter().eq(model.iter().copied()));
+                let mut indices = Vec::new();
+                set.iter().for_each(|index| indices.push(index));
+                assert!(indices.iter().eq(model.iter()));
+                assert!(set.iter().rev().eq(model.iter().rev().copied()));
+                indices.clear();
+                set.iter().rev().for_each(|index| indices.push(index));
+                assert!(indices.iter().eq(model.iter().rev()));
+                assert_eq!(set.first(), model.first().copied());
+                assert_eq!(set.last(), model.last().copied());
+                assert_eq!(set.len(), model.len());
+                assert_eq!(set.is_empty(), model.is_empty());
+                let stats = set.stats();
+                assert_eq!(stats.len, model.len());
+                assert_eq!(stats.bytes, set.memory_usage());
+                assert!(stats.bottom.blocks <= model.len());
+                for level in [stats.top, stats.middle, stats.bottom] {
+                    assert!((0.0..=1.0).contains(&level.fill_ratio));
+                }
+            }
+            Op::IterRange(side, start, end) => {
+                let set = &self.sets[side];
+                let model = &self.models[side];
+                assert!(
+                    set.iter_range(start..end)
+                        .eq(model.range(start..end).copied())
+                );
+                assert!(
+                    set.iter_range(start..=end)
+                        .rev()
+                        .eq(model.range(start..=end).rev().copied())
+                );
+                let mut indices = Vec::new();
+                set.iter_range(start..)
+                    .for_each(|index| indices.push(index));
+                assert!(indices.iter().eq(model.range(start..)));
+                indices.clear();
+                set.iter_range(..=end)
+                    .rev()
+                    .for_each(|index| indices.push(index));
+                assert!(indices.iter().eq(model.range(..=end).rev()));
+            }
+            Op::IterBothEnds(side, from_back) => {
+                let mut iter = self.sets[side].iter();
+                let mut model = self.models[side].iter().copied();
+                for from_back in from_back {
+                    if from_back {
+                        assert_eq!(iter.next_back(), model.next_back());
+                    } else {
+                        assert_eq!(iter.next(), model.next());
+                    }
+                }
+                let rest = iter.clone().fold(Vec::new(), |mut rest, index| {
+                    rest.push(index);
+                    rest
+                });
+                assert!(rest.into_iter().eq(model.clone()));
+                assert!(iter.eq(model));
+            }
+            Op::Snapshot => {
+                self.check_snapshots();
+                if self.snapshots.len() == 8 {
+                    self.snapshots.remove(0);
+                }
+                let snapshot = (self.cow.clone(), self.cow_model.clone());
+                self.snapshots.push(snapshot);
+            }
+            Op::SetOps => self.check_set_ops(),
+        }
+    }
+
+    fn check_snapshots(&self) {
+        assert!(self.cow.iter().eq(self.cow_model.iter().copied()));
+        assert_eq!(self.cow.len(), self.cow_model.len());
+        for (snapshot, model) in &self.snapshots {
+            assert!(snapshot.iter().eq(model.iter().copied()));
+            assert!(snapshot.iter().rev().eq(model.iter().rev().copied()));
+            assert_eq!(snapshot.len(), model.len());
+            assert_eq!(snapshot == &self.cow, model == &self.cow_model);
+            assert_eq!(
+                inbitset::diff(snapshot, &self.cow).count(),
+                model.symmetric_difference(&self.cow_model).count()
+            );
+            assert!(
+                snapshot
+                    .diff(&self.cow)
+                    .eq(inbitset::diff(snapshot, &self.cow))
+            );
+            assert!(
+                self.cow
+                    .diff(snapshot)
+                    .eq(inbitset::diff(&self.cow, snapshot))
+            );
+        }
+    }
+
+    fn check_set_ops(&self) {
+        let [a, b] = &self.sets;
+        let [ma, mb] = &self.models;
+
+        assert!(a.and(b).iter().eq(ma.intersection(mb).copied()));
+        assert!(a.or(b).iter().eq(ma.union(mb).copied()));
+        assert!(a.xor(b).iter().eq(ma.symmetric_difference(mb).copied()));
+        assert!(a.and_not(b).iter().eq(ma.difference(mb).copied()));
+        let intersection: Vec<_> = ma.intersection(mb).copied().collect();
+        assert!(a.and(b).iter().rev().eq(intersection.into_iter().rev()));
+        let difference: Vec<_> = ma.difference(mb).copied().collect();
+        assert!(a.and_not(b).iter().rev().eq(difference.into_iter().rev()));
+
+        assert_eq!(a.is_subset(b), ma.is_subset(mb));
+        assert_eq!(a.is_superset(b), ma.is_superset(mb));
+        assert_eq!(a.is_disjoint(b), ma.is_disjoint(mb));
+        assert_eq!(a == b, ma == mb);
+        let reversed: BitSet<Compact, LANES> = ma.iter().rev().copied().collect();
+        assert_eq!(&reversed, a);
+
+        let collected: BitSet<Wide, LANES> = a.iter().chain(b.iter()).collect();
+        assert!(collected.iter().eq(ma.union(mb).copied()));
+        assert_eq!(collected.len(), ma.union(mb).count());
+        assert!(a.is_subset(&collected) && collected.is_superset(b));
+        assert!(a.and_not(b).materialize().is_disjoint(b));
+
+        let bytes = a.to_bytes();
+        let a_ref = BitSetRef::<LANES>::new(&bytes).unwrap();
+        assert!(a_ref.iter().eq(ma.iter().copied()));
+        assert!(a_ref.iter().rev().eq(ma.iter().rev().copied()));
+        assert!(a_ref.and(b).iter().eq(ma.intersection(mb).copied()));
+        assert_eq!(&BitSet::<Wide, LANES>::from_bytes(&bytes).unwrap(), a);
+        assert_eq!(reversed.to_bytes(), bytes);
+
+        let xor = a.xor(b).materialize();
+        assert!(xor.iter().eq(ma.symmetric_difference(mb).copied()));
+        assert_eq!(xor.len(), ma.symmetric_difference(mb).count());
+        assert_eq!(
+            xor.rank(usize::MAX),
+            xor.iter().filter(|&i| i != usize::MAX).count()
+        );
+
+        let changes: Vec<_> = inbitset::diff(a, b).collect();
+        let expected: Vec<_> = ma
+            .symmetric_difference(mb)
+            .map(|&index| {
+                if mb.contains(&index) {
+                    Change::Added(index)
+                } else {
+                    Change::Removed(index)
+                }
+            })
+            .collect();
+        assert_eq!(changes, expected);
+        let mut patched = a.clone();
+        for change in changes {
+            match change {
+                Change::Added(index) => assert!(patched.insert(index)),
+                Change::Removed(index) => assert!(patched.remove(index)),
+            }
+        }
+        assert_eq!(&patched, b);
+        assert_eq!(inbitset::diff(&xor, a.xor(b)).next(), None);
+
+        let union = inbitset::reduce(inbitset::ops::Or, [a, b, &xor]);
+        assert!(union.iter().eq(ma.union(mb).copied()));
+        let model_union: Vec<_> = ma.union(mb).copied().collect();
+        assert!(union.iter().rev().eq(model_union.into_iter().rev()));
+        let intersection = inbitset::reduce(inbitset::ops::And, [a, b, &xor]);
+        assert_eq!(intersection.iter().next(), None);
+    }
+}
+
+fn run<const LANES: usize>(ops: Vec<Op>) {
+    let mut model = Model::<LANES>::default();
+    for op in ops {
+        model.apply(op);
+    }
+    model.apply(Op::Iter(0));
+    model.apply(Op::Iter(1));
+    model.apply(Op::SetOps);
+    model.check_snapshots();
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(512))]
+
+    #[test]
+    fn matches_btree_set(ops in prop::collection::vec(op(256), 1..400)) {
+        run::<4>(ops);
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(128))]
+
+    #[test]
+    fn matches_btree_set_64bit(ops in prop::collection::vec(op(64), 1..400)) {
+        run::<1>(ops);
+    }
+
+    #[test]
+    fn matches_btree_set_128bit(ops in prop::collection::vec(op(128), 1..400)) {
+        run::<2>(ops);
+    }
+
+    #[test]
+    fn matches_btree_set_512bit(ops in prop::collection::vec(op(512), 1..400)) {
+        run::<8>(ops);
+    }
+}
+
+#[test]
+fn reuses_freed_blocks() {
+    let mut set = BitSet::new();
+    let mut model = BTreeSet::new();
+    for round in 0..16 {
+        for index in (round * 1000..round * 1000 + 70_000).step_by(97) {
+            assert_eq!(set.insert(index), model.insert(index));
+        }
+        for index in (round * 1000..round * 1000 + 70_000).step_by(89) {
+            assert_eq!(set.remove(index), model.remove(&index));
+        }
+        assert!(set.iter().eq(model.iter().copied()));
+    }
+    while let Some(index) = set.pop_first() {
+        assert_eq!(Some(index), model.pop_first());
+    }
+    assert!(model.is_empty());
+    assert!(set.is_empty());
+}
+
+#[test]
+fn ranges_span_blocks() {
+    let mut set = BitSet::new();
+    set.insert_range(1000..200_000);
+    set.insert_range(usize::MAX - 1000..);
+    assert_eq!(set.len(), 199_000 + 1001);
+    assert!(
+        set.iter()
+            .eq((1000..200_000).chain(usize::MAX - 1000..=usize::MAX))
+    );
+
+    set.remove_range(100_000..=usize::MAX - 10);
+    assert!(
+        set.iter()
+            .eq((1000..100_000).chain(usize::MAX - 9..=usize::MAX))
+    );
+    set.remove_range(..);
+    assert!(set.is_empty());
+    assert_eq!(set.iter().next(), None);
+
+    let set: BitSet = BitSet::from_sorted_iter((0..70_000).step_by(3));
+    assert!(set.iter().eq((0..70_000).step_by(3)));
+}
+
+#[test]
+fn compact_releases_freed_blocks() {
+    let mut set = BitSet::new();
+    set.insert_range(0..1 << 20);
+    let full = set.memory_usage();
+    set.remove_range(1000..(1 << 20) - 1000);
+    set.insert(1 << 19);
+    assert_eq!(set.memory_usage(), full);
+
+    let before = set.clone();
+    set.compact();
+    assert!(set.memory_usage() < full / 10);
+    assert_eq!(set, before);
+    assert!(
+        set.iter()
+            .eq((0..1000).chain([1 << 19]).chain((1 << 20) - 1000..1 << 20))
+    );
+
+    set.insert_range(5000..6000);
+    set.remove(1 << 19);
+    assert_eq!(set.len(), 3000);
+}
+
+#[test]
+fn clear_keeps_blocks_for_reuse() {
+    let mut set = BitSet::new();
+    for round in 0..4 {
+        set.insert_range(round * 1000..70_000);
+        set.insert(usize::MAX - round);
+        let full = set.memory_usage();
+        set.clear();
+        assert!(set.is_empty());
+        assert_eq!(set.iter().next(), None);
+        assert_eq!(set.memory_usage(), full);
+    }
+
+    set.extend((0..100_000).step_by(3));
+    let mut drain = set.drain();
+    assert_eq!(drain.next(), Some(0));
+    assert_eq!(drain.next(), Some(3));
+    drop(drain);
+    assert!(set.is_empty());
+    set.insert(5);
+    assert!(set.iter().eq([5]));
+}
+
+#[test]
+fn debug_and_stats() {
+    let mut set = BitSet::new();
+    assert_eq!(format!("{set:?}"), "{}");
+    set.insert_range(0..10);
+    set.extend([15, 300, usize::MAX - 1, usize::MAX]);
+    assert_eq!(
+        format!("{set:?}"),
+        format!("{{0..=9, 15, 300, {}..={}}}", usize::MAX - 1, usize::MAX)
+    );
+    assert_eq!(format!("{:?}", set.top_bits(0)), "{0}");
+    let tree = format!("{set:#?}");
+    assert!(tree.contains("TopBlock") && tree.contains("0..=9"));
+
+    let stats = set.stats();
+    assert_eq!(stats.len, 14);
+    assert_eq!((stats.top.blocks, stats.middle.blocks), (2, 2));
+    assert_eq!(stats.bottom.blocks, 3);
+    assert_eq!(stats.bottom.fill_ratio, 14.0 / (3.0 * 256.0));
+
+    set.remove(300);
+    let stats = set.stats();
+    assert_eq!((stats.bottom.blocks, stats.bottom.free), (2, 1));
+    assert_eq!(stats.bytes, set.memory_usage());
+}
+
+#[test]
+fn cow_clones_share_untouched_blocks() {
+    // 64 bottom blocks under 4 middle blocks of a single top block.
+    let set: CowBitSet = (0..64).map(|i| i * 4096).collect();
+    assert_eq!(set.shared_blocks(&set.clone()), 68);
+    assert_eq!(set.shared_blocks(&set.iter().collect()), 0);
+
+    // Only the middle and bottom block on the path to the new bit are copied.
+    let mut clone = set.clone();
+    assert!(clone.insert(1));
+    assert_eq!(set.shared_blocks(&clone), 66);
+    assert!(!clone.insert(1) && !clone.remove(2));
+    assert_eq!(clone.shared_blocks(&set), 66);
+
+    // A new top block leaves all the old blocks shared.
+    let mut clone = set.clone();
+    clone.insert(usize::MAX);
+    assert_eq!(set.shared_blocks(&clone), 68);
+    let changes: Vec<_> = set.diff(&clone).collect();
+    assert_eq!(changes, [Change::Added(usize::MAX)]);
+}
+
+#[test]
+fn running_out_of_indices_leaves_the_set_intact() {
+    // Every bit in its own bottom block, until the 16-bit indices of the middle blocks run out.
+    let mut set = BitSet::<Compact>::from_sorted_iter((0..1 << 16).map(|i| i * 256));
+    let full = set.clone();
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        set.insert(1 << 24);
+    }));
+    assert!(result.is_err());
+    assert_eq!(set, full);
+    assert_eq!(set.len(), 1 << 16);
+    assert!(!set.contains(1 << 24));
+    assert_eq!(BitSet::<Compact>::from_bytes(&set.to_bytes()), Ok(full));
+
+    // Existing blocks can still take more bits, and freed ones can be reused.
+    assert!(set.insert(1));
+    assert!(set.remove(0) && set.remove(1));
+    assert!(set.insert(1 << 24));
+    set.compact();
+    assert!(
+        set.iter()
+            .eq((1..1 << 16).map(|i| i * 256).chain([1 << 24]))
+    );
+}
+
+#[test]
+fn many_top_blocks() {
+    // Every bit in its own top block, inserted in scattered order.
+    let keys = 1 << 14;
+    let index = |i: usize| (((i * 40503) % keys) << 24) | (i % 1000);
+    let mut set = BitSet::new();
+    for i in 0..keys {
+        assert!(set.insert(index(i)));
+    }
+    let sorted: BTreeSet<_> = (0..keys).map(index).collect();
+    assert_eq!(set.len(), keys);
+    assert!(set.iter().eq(sorted.iter().copied()));
+    assert!(set.iter().rev().eq(sorted.iter().rev().copied()));
+    assert_eq!(set.stats().top.blocks, keys);
+
+    // Emptied top blocks are put on the free list and reused.
+    for i in (0..keys).step_by(2) {
+        assert!(set.remove(index(i)));
+    }
+    assert_eq!(
+        (set.stats().top.blocks, set.stats().top.free),
+        (keys / 2, keys / 2)
+    );
+    for (n, i) in set.iter().enumerate() {
+        assert_eq!((set.rank(i), set.select(n)), (n, Some(i)));
+    }
+    assert_eq!(
+        (set.rank(usize::MAX), set.select(keys / 2)),
+        (keys / 2, None)
+    );
+    for i in (0..keys).step_by(2) {
+        assert!(set.insert(index(i)));
+    }
+    assert_eq!(set.stats().top.free, 0);
+    assert!(set.iter().eq(sorted.iter().copied()));
+    set.compact();
+    assert_eq!(BitSet::from_bytes(&set.to_bytes()), Ok(set));
+}
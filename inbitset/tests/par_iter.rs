@@ -0,0 +1,37 @@
+//! Parallel iteration, checked against the sequential iterator.
+
+#![cfg(feature = "rayon")]
+
+use inbitset::{BitSet, BitSetView};
+use rayon::prelude::*;
+
+fn collect_sorted(iter: impl ParallelIterator<Item = usize>) -> Vec<usize> {
+    let mut indices: Vec<_> = iter.collect();
+    indices.sort_unstable();
+    indices
+}
+
+#[test]
+fn matches_iter() {
+    let mut a = BitSet::new();
+    a.insert_range(0..1_000_000);
+    a.extend((3_000_000..5_000_000).step_by(7));
+    a.extend([1 << 40, (1 << 40) + 1, usize::MAX]);
+    // Many top blocks with one bit each, so the key range gets split too.
+    a.extend((1..1000).map(|i| i << 44));
+    let mut b = BitSet::new();
+    b.extend((0..6_000_000).step_by(3));
+
+    assert_eq!(collect_sorted(a.par_iter()), a.iter().collect::<Vec<_>>());
+    assert_eq!(a.par_iter().count(), a.len());
+    let and = (&a).and(&b);
+    assert_eq!(
+        collect_sorted(and.par_iter()),
+        and.iter().collect::<Vec<_>>()
+    );
+    let xor = (&a).xor(&b);
+    assert_eq!(xor.par_iter().count(), xor.iter().count());
+
+    assert_eq!(BitSet::new().par_iter().count(), 0);
+    assert!(a.par_iter().any(|index| index == usize::MAX));
+}
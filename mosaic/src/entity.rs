@@ -1,7 +1,7 @@
 use std::collections::HashMap;
 
 use crate::{
-    BitSet, Handle, Index,
+    BitSet, Handle, Index, mask,
     store::{MaskStore, Store, VecStore},
 };
 
@@ -46,9 +46,7 @@ pub struct IndexAlloc {
 
 impl IndexAlloc {
     pub fn allocate(&mut self) -> Index {
-        let freed = self.freed.iter().next();
-        if let Some(index) = freed {
-            self.freed.remove(index);
+        if let Some(index) = mask::pop_first(&mut self.freed) {
             index
         } else {
             let index = self.next;
@@ -0,0 +1,101 @@
+use crate::Index;
+
+/// Bitset of entity indices, as used by stores and queries. Implemented by the selected bitset
+/// backend for its sets and lazy set operations.
+pub trait Mask: Sized {
+    type Indices: Iterator<Item = Index>;
+
+    fn contains(&self, index: Index) -> bool;
+    fn into_indices(self) -> Self::Indices;
+}
+
+/// Lazy intersection of two masks.
+pub trait MaskAnd<M: Mask>: Mask {
+    type Output: Mask;
+
+    fn mask_and(self, other: M) -> Self::Output;
+}
+
+#[cfg(not(any(feature = "hi_sparse_bitset", feature = "inbitset")))]
+compile_error!("one of the features `hi_sparse_bitset` or `inbitset` has to be enabled");
+
+// Features are additive, so `inbitset` takes precedence when both backends are enabled, for
+// example by one crate keeping the defaults and another opting into `inbitset`.
+#[cfg(all(feature = "hi_sparse_bitset", not(feature = "inbitset")))]
+mod backend {
+    use hi_sparse_bitset::{BitSetInterface, iter::IndexIter, ops::And};
+
+    use super::{Mask, MaskAnd};
+    use crate::Index;
+
+    pub type BitSet = hi_sparse_bitset::BitSet<hi_sparse_bitset::config::_128bit>;
+
+    /// Unsets the lowest set index and returns it.
+    pub fn pop_first(set: &mut BitSet) -> Option<Index> {
+        let index = set.iter().next()?;
+        set.remove(index);
+        Some(index)
+    }
+
+    impl<T: BitSetInterface> Mask for T {
+        type Indices = IndexIter<T>;
+
+        fn contains(&self, index: Index) -> bool {
+            BitSetInterface::contains(self, index)
+        }
+
+        fn into_indices(self) -> Self::Indices {
+            self.into_block_iter().into_indices()
+        }
+    }
+
+    impl<A, B> MaskAnd<B> for A
+    where
+        A: BitSetInterface,
+        B: BitSetInterface<Conf = A::Conf>,
+    {
+        type Output = hi_sparse_bitset::Apply<And, A, B>;
+
+        fn mask_and(self, other: B) -> Self::Output {
+            hi_sparse_bitset::apply(And, self, other)
+        }
+    }
+}
+
+#[cfg(feature = "inbitset")]
+mod backend {
+    use inbitset::{BitSetView, Iter, ops::And};
+
+    use super::{Mask, MaskAnd};
+    use crate::Index;
+
+    pub type BitSet = inbitset::BitSet;
+
+    /// Unsets the lowest set index and returns it.
+    pub fn pop_first(set: &mut BitSet) -> Option<Index> {
+        set.pop_first()
+    }
+
+    impl<T: BitSetView> Mask for T {
+        type Indices = Iter<T>;
+
+        fn contains(&self, index: Index) -> bool {
+            BitSetView::contains(self, index)
+        }
+
+        fn into_indices(self) -> Self::Indices {
+            Iter::new(self)
+        }
+    }
+
+    impl<A: BitSetView, B: BitSetView> MaskAnd<B> for A {
+        type Output = inbitset::ops::Apply<And, A, B>;
+
+        fn mask_and(self, other: B) -> Self::Output {
+            inbitset::apply(And, self, other)
+        }
+    }
+}
+
+pub use backend::BitSet;
+pub(crate) use backend::pop_first;
@@ -1,14 +1,13 @@
-use hi_sparse_bitset::{BitSetInterface, iter::IndexIter};
-
 use crate::{
     BitSet, Index,
+    mask::{Mask, MaskAnd},
     store::{MaskStore, RawStore, Store},
 };
 
 pub trait Query {
     type Item;
     type Access;
-    type Mask: BitSetInterface;
+    type Mask: Mask;
 
     fn open(self) -> (Self::Mask, Self::Access);
 
@@ -46,14 +45,14 @@ impl<Q: Query> IntoQuery for Q {
 }
 
 pub struct QueryIter<Q: Query> {
-    mask_iter: IndexIter<Q::Mask>,
+    mask_iter: <Q::Mask as Mask>::Indices,
     access: Q::Access,
 }
 
 impl<Q: Query> QueryIter<Q> {
     pub fn new(query: Q) -> Self {
         let (mask, access) = query.open();
-        let mask_iter = mask.into_block_iter().into_indices();
+        let mask_iter = mask.into_indices();
         Self { mask_iter, access }
     }
 }
@@ -108,7 +107,7 @@ impl<Q: Query> Query for MaybeQuery<Q> {
 pub struct QueryTuple<T>(T);
 
 pub trait BitSetAnd {
-    type Value: BitSetInterface;
+    type Value: Mask;
 
     fn bitset_and(self) -> Self::Value;
 }
@@ -146,7 +145,8 @@ macro_rules! define_query {
         impl<$first, $($rest),*> Query for QueryTuple<($first, $($rest),*)>
         where
             $first: Query,
-            $($rest: Query<Mask: hi_sparse_bitset::BitSetBase<Conf = <$first::Mask as hi_sparse_bitset::BitSetBase>::Conf>>,)*
+            $($rest: Query,)*
+            (<$first as Query>::Mask, $(<$rest as Query>::Mask),*): BitSetAnd,
         {
             type Item = ($first::Item, $($rest::Item),*);
             type Access = ($first::Access, $($rest::Access),*);
@@ -181,7 +181,8 @@ macro_rules! define_into_query {
         impl<$first, $($rest),*> IntoQuery for ($first, $($rest),*)
         where
             $first: IntoQuery,
-            $($rest: IntoQuery<IntoQuery: Query<Mask: hi_sparse_bitset::BitSetBase<Conf = <<$first::IntoQuery as Query>::Mask as hi_sparse_bitset::BitSetBase>::Conf>>>,)*
+            $($rest: IntoQuery,)*
+            QueryTuple<(<$first as IntoQuery>::IntoQuery, $(<$rest as IntoQuery>::IntoQuery),*)>: Query<Item = ($first::Item, $($rest::Item),*)>,
         {
             type Item = ($first::Item, $($rest::Item),*);
             type IntoQuery = QueryTuple<(<$first as IntoQuery>::IntoQuery, $(<$rest as IntoQuery>::IntoQuery),*)>;
@@ -204,15 +205,15 @@ macro_rules! define_bitset_and {
     ($first:ident, $($rest:ident),+ $(,)?) => {
         impl<$first, $($rest),*> BitSetAnd for ($first, $($rest),*)
         where
-            $first: hi_sparse_bitset::BitSetInterface,
-            $($rest: hi_sparse_bitset::BitSetInterface<Conf = $first::Conf>),*
+            ($($rest,)*): BitSetAnd,
+            $first: MaskAnd<<($($rest,)*) as BitSetAnd>::Value>,
         {
-            type Value = hi_sparse_bitset::Apply<hi_sparse_bitset::ops::And, $first, <($($rest,)*) as BitSetAnd>::Value>;
+            type Value = <$first as MaskAnd<<($($rest,)*) as BitSetAnd>::Value>>::Output;
 
             #[allow(non_snake_case)]
             fn bitset_and(self) -> Self::Value {
                 let ($first, $($rest),*) = self;
-                hi_sparse_bitset::apply(hi_sparse_bitset::ops::And, $first, ($($rest,)*).bitset_and())
+                $first.mask_and(($($rest,)*).bitset_and())
             }
         }
     };
@@ -220,7 +221,7 @@ macro_rules! define_bitset_and {
     ($first:ident $(,)?) => {
         impl<$first> BitSetAnd for ($first,)
         where
-            $first: hi_sparse_bitset::BitSetInterface,
+            $first: Mask,
         {
             type Value = $first;
 